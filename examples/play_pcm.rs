@@ -23,10 +23,9 @@ fn main() {
     .unwrap();
 
     for sample in samples.chunks(2) {
-        audio
-            .output(&[sample[0] as f64 / 32768.0, sample[1] as f64 / 32768.0])
-            .unwrap();
+        audio.output(sample).unwrap();
     }
+    audio.drain().unwrap();
 
     #[cfg(target_os = "windows")]
     unsafe {
@@ -1,150 +1,760 @@
 use std::ffi::CString;
+use std::io;
+use std::ops::RangeInclusive;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use alsa::{
+    card::Card,
     device_name::HintIter,
-    pcm::{Access, Format, Frames, HwParams},
+    pcm::{Access, Format, Frames, HwParams, State, TstampType},
     Direction, ValueOr, PCM,
 };
 
-use super::AudioDriver;
+use super::{
+    ring_buffer, sample, AudioClock, AudioConfig, AudioDriver, AudioInputDriver, AudioStats,
+    Consumer, DeviceCapabilities, DeviceInfo, DeviceSource, ErrorKind, Producer, SampleFormat,
+    Speaker, StreamFormat,
+};
 
 pub use alsa::Error;
 
+impl From<Error> for super::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err.errno() as i32 {
+            libc::EPIPE | libc::ESTRPIPE => ErrorKind::Xrun,
+            libc::ENODEV | libc::ENXIO => ErrorKind::DeviceLost,
+            libc::EBUSY => ErrorKind::DeviceBusy,
+            libc::EINVAL | libc::ENOENT => ErrorKind::InvalidConfig,
+            _ => ErrorKind::Backend,
+        };
+        super::Error::with_source(kind, err)
+    }
+}
+
+// formats tried in order when opening a device
+const FORMATS: [SampleFormat; 6] = [
+    SampleFormat::S16,
+    SampleFormat::F32,
+    SampleFormat::S32,
+    SampleFormat::S24,
+    SampleFormat::U8,
+    SampleFormat::F64,
+];
+
+// rates offered to the user, devices with a continuous range accept them all
+const FREQUENCIES: [u32; 11] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000,
+];
+
+// plugins claim to take any channel count, nothing past 7.1 is useful
+const MAX_CHANNELS: u32 = 8;
+
+// buffer lengths in ms offered to the user
+const LATENCIES: [u32; 8] = [5, 10, 20, 40, 60, 80, 100, 200];
+
+// the device's whole configuration space, not just the part in use
+fn hw_params_any(pcm: &PCM) -> Result<HwParams<'_>, Error> {
+    let hw_params = HwParams::any(pcm)?;
+    hw_params.set_access(Access::RWInterleaved)?;
+    Ok(hw_params)
+}
+
+// the buffer has to fit the device and so do the 8 periods it's split into
+fn fits_latency(hw_params: &HwParams, latency: u32, frequency: u32) -> bool {
+    let (Ok(buffer_min), Ok(buffer_max), Ok(period_min), Ok(period_max)) = (
+        hw_params.get_buffer_time_min(),
+        hw_params.get_buffer_time_max(),
+        hw_params.get_period_size_min(),
+        hw_params.get_period_size_max(),
+    ) else {
+        return false;
+    };
+
+    let buffer_time = latency * 1000; // ms -> us
+    let period_size = (buffer_time as u64 / 8 * frequency as u64 / 1_000_000) as Frames;
+    (buffer_min..=buffer_max).contains(&buffer_time)
+        && (period_min..=period_max).contains(&period_size)
+}
+
+// Narrows the configuration space one requested field at a time, so values
+// the device only takes apart from each other are rejected too. `frequency`
+// sizes the periods when the config doesn't ask for a rate.
+fn check(pcm: &PCM, config: &AudioConfig, frequency: u32) -> Result<(), super::Error> {
+    let hw_params = hw_params_any(pcm)?;
+    let invalid = |what: String| super::Error::new(ErrorKind::InvalidConfig, what);
+
+    if let Some(format) = config.format {
+        hw_params
+            .set_format(alsa_format(format))
+            .map_err(|_| super::Error::new(ErrorKind::FormatUnsupported, format.to_string()))?;
+    }
+    if let Some(channels) = config.channels {
+        hw_params
+            .set_channels(channels)
+            .map_err(|_| invalid(format!("channels: {}", channels)))?;
+    }
+    if let Some(frequency) = config.frequency {
+        hw_params
+            .set_rate(frequency, ValueOr::Nearest)
+            .map_err(|_| invalid(format!("frequency: {}", frequency)))?;
+    }
+    if let Some(latency) = config.latency {
+        let frequency = config.frequency.unwrap_or(frequency);
+        if !fits_latency(&hw_params, latency, frequency) {
+            return Err(invalid(format!("latency: {}", latency)));
+        }
+    }
+
+    Ok(())
+}
+
+// A stream as `configure` sees it, `format` is the one asked for and `None`
+// picks the first of `FORMATS` the device has.
+#[derive(Clone)]
+struct Settings {
+    device: String,
+    frequency: u32,
+    channels: u32,
+    format: Option<SampleFormat>,
+    latency: u32,
+    blocking: bool,
+}
+
+// Fills in what `config` leaves out from the open stream, `None` when nothing
+// would change, `format` is the one the stream actually runs at. A config for
+// the same device is checked on the open stream before it is touched, another
+// device is checked by `open` once it has it, a second handle on a card that
+// is already open would only get EBUSY.
+fn resolve(
+    config: &AudioConfig,
+    devices: &[DeviceInfo],
+    pcm: Option<&PCM>,
+    current: Settings,
+    format: SampleFormat,
+) -> Result<Option<Settings>, super::Error> {
+    let device = config.device.as_deref().unwrap_or(&current.device);
+    if !devices.iter().any(|it| it.id == device) {
+        return Err(super::Error::new(
+            ErrorKind::InvalidConfig,
+            format!("Device {} not found", device),
+        ));
+    }
+
+    if config.exclusive == Some(true) {
+        return Err(super::Error::new(ErrorKind::InvalidConfig, "exclusive"));
+    }
+
+    let target = Settings {
+        device: device.to_string(),
+        frequency: config.frequency.unwrap_or(current.frequency),
+        channels: config.channels.unwrap_or(current.channels),
+        format: config.format.or(current.format),
+        latency: config.latency.unwrap_or(current.latency),
+        blocking: config.blocking.unwrap_or(current.blocking),
+    };
+
+    if target.device == current.device
+        && target.frequency == current.frequency
+        && target.channels == current.channels
+        && target.format.is_none_or(|it| it == format)
+        && target.latency == current.latency
+        && target.blocking == current.blocking
+    {
+        return Ok(None);
+    }
+
+    if let Some(pcm) = pcm.filter(|_| target.device == current.device) {
+        check(pcm, config, target.frequency)?;
+    }
+    Ok(Some(target))
+}
+
+// the card a pcm name points at, `hw:0,0` and `plughw:CARD=PCH,DEV=0` alike,
+// `None` for plugins like `default` that don't name one
+fn card(name: &str) -> Option<i32> {
+    let (_, args) = name.split_once(':')?;
+    let card = args
+        .split(',')
+        .find_map(|arg| arg.strip_prefix("CARD="))
+        .or_else(|| args.split(',').next().filter(|arg| !arg.contains('=')))?;
+    let card = CString::new(card.trim_matches('"')).ok()?;
+    Card::from_str(&card).ok().map(|card| card.get_index())
+}
+
+// hw devices open only once and the plugins on top of them hold them too, so
+// a stream has to let go of its card before another pcm on it can open
+fn shares_card(a: &str, b: &str) -> bool {
+    a == b || card(a).is_some_and(|card_a| card(b) == Some(card_a))
+}
+
+fn closed() -> super::Error {
+    super::Error::new(ErrorKind::DeviceLost, "Stream closed")
+}
+
+fn probe(pcm: &PCM) -> Result<DeviceCapabilities, Error> {
+    let hw_params = hw_params_any(pcm)?;
+    let range = |min: alsa::Result<u32>, max: alsa::Result<u32>| Some(min.ok()?..=max.ok()?);
+    let micros = |time: u32| Duration::from_micros(time as u64);
+
+    let frequency_range = range(hw_params.get_rate_min(), hw_params.get_rate_max());
+    let buffer_time_range = range(
+        hw_params.get_buffer_time_min(),
+        hw_params.get_buffer_time_max(),
+    )
+    .map(|range| micros(*range.start())..=micros(*range.end()));
+    let period_time_range = frequency_range
+        .clone()
+        .and_then(|rates| period_time_range(&hw_params, rates));
+
+    Ok(DeviceCapabilities {
+        channels: (1..=MAX_CHANNELS)
+            .filter(|&channels| hw_params.test_channels(channels).is_ok())
+            .collect(),
+        frequencies: FREQUENCIES
+            .into_iter()
+            .filter(|&rate| hw_params.test_rate(rate).is_ok())
+            .collect(),
+        formats: FORMATS
+            .into_iter()
+            .filter(|&format| hw_params.test_format(alsa_format(format)).is_ok())
+            .collect(),
+        channel_range: range(hw_params.get_channels_min(), hw_params.get_channels_max()),
+        frequency_range,
+        buffer_time_range,
+        period_time_range,
+    })
+}
+
+// only period sizes are exposed, the shortest period runs at the highest
+// rate and the longest at the lowest
+fn period_time_range(
+    hw_params: &HwParams,
+    rates: RangeInclusive<u32>,
+) -> Option<RangeInclusive<Duration>> {
+    let min = hw_params.get_period_size_min().ok()? as f64 / *rates.end() as f64;
+    let max = hw_params.get_period_size_max().ok()? as f64 / (*rates.start()).max(1) as f64;
+    Some(Duration::from_secs_f64(min)..=Duration::from_secs_f64(max))
+}
+
+// opens every device but the one in use to probe it, busy ones report no
+// capabilities
+fn probe_devices(
+    devices: &[DeviceInfo],
+    direction: Direction,
+    name: &str,
+    pcm: Option<&PCM>,
+) -> Vec<DeviceInfo> {
+    let mut devices = devices.to_vec();
+    for device in &mut devices {
+        let capabilities = if let Some(pcm) = pcm.filter(|_| device.id == name) {
+            probe(pcm)
+        } else {
+            PCM::new(&device.id, direction, true).and_then(|pcm| probe(&pcm))
+        };
+        device.capabilities = capabilities.unwrap_or_default();
+    }
+    devices
+}
+
+fn alsa_format(format: SampleFormat) -> Format {
+    match format {
+        SampleFormat::U8 => Format::U8,
+        SampleFormat::S16 => Format::S16LE,
+        SampleFormat::S24 => Format::S24LE,
+        SampleFormat::S32 => Format::S32LE,
+        SampleFormat::F32 => Format::FloatLE,
+        SampleFormat::F64 => Format::Float64LE,
+    }
+}
+
+// restarts the stream after an xrun or a suspend and counts it in `xruns`,
+// anything else is passed on
+fn recover(pcm: &PCM, xruns: &mut u64, err: Error) -> Result<(), Error> {
+    pcm.recover(err.errno() as i32, true)?;
+    *xruns += 1;
+    Ok(())
+}
+
+// Opens `settings.device` and settles the hardware parameters, keeping what
+// the device granted since the rest of the pipeline runs on it. What `config`
+// asks for explicitly has to be there, the rest is negotiated.
+fn open(
+    settings: &Settings,
+    direction: Direction,
+    config: &AudioConfig,
+) -> Result<(PCM, StreamFormat), super::Error> {
+    let name = &settings.device;
+    let pcm = PCM::new(name, direction, !settings.blocking)?;
+    check(&pcm, config, settings.frequency)?;
+
+    let rate = settings.frequency;
+    let buffer_time = settings.latency * 1000; // ms -> us
+    let period_time = buffer_time / 8; // ms -> us
+
+    let hw_params = hw_params_any(&pcm)?;
+    let formats = settings
+        .format
+        .map_or(FORMATS.to_vec(), |format| vec![format]);
+    let format = formats
+        .into_iter()
+        .find(|&format| hw_params.test_format(alsa_format(format)).is_ok())
+        .ok_or_else(|| {
+            super::Error::new(
+                ErrorKind::FormatUnsupported,
+                format!("{}: no sample format", name),
+            )
+        })?;
+    hw_params.set_format(alsa_format(format))?;
+    let channels = hw_params.set_channels_near(settings.channels)?;
+    let frequency = hw_params.set_rate_near(rate, ValueOr::Nearest)?;
+    hw_params.set_buffer_time_near(buffer_time, ValueOr::Nearest)?;
+    hw_params.set_period_time_near(period_time, ValueOr::Nearest)?;
+    pcm.hw_params(&hw_params)?;
+    drop(hw_params);
+
+    let (buffer_size, period_size) = pcm.get_params()?;
+    let stream = StreamFormat {
+        frequency,
+        channels,
+        format,
+        period_size: period_size as usize,
+        buffer_size: buffer_size as usize,
+    };
+    Ok((pcm, stream))
+}
+
+// the buffer length in ms, rounded
+fn latency_ms(buffer_size: u64, frequency: u32) -> u32 {
+    ((buffer_size * 1000 + frequency as u64 / 2) / frequency as u64) as u32
+}
+
 struct ALSADriverPrev {
     blocking: bool,
-    buffer: Vec<i16>,
-    _buffer_size: u64,
+    buffer: Vec<f32>,
+    buffer_size: u64,
+    bytes: Vec<u8>,
+    channels: u32,
+    consumer: Consumer<f32>,
+    format: SampleFormat,
     frequency: u32,
     latency: u32,
+    monotonic: bool,
     name: String,
-    pcm: PCM,
+    // `None` while `configure` has let go of the card for another pcm on it
+    pcm: Option<PCM>,
     period_size: u64,
+    producer: Producer<f32>,
+    stats: AudioStats,
+    // frames handed to the device, counted on from the streams before
+    written: u64,
 }
 
 impl ALSADriverPrev {
-    fn new(
-        name: &str,
-        latency: u32,
-        frequency: u32,
-        blocking: bool,
-    ) -> Result<ALSADriverPrev, super::Error> {
-        let pcm = PCM::new(&name, Direction::Playback, !blocking)?;
-
-        let rate = frequency;
-        let buffer_time = latency * 1000; // ms -> us
-        let period_time = buffer_time / 8; // ms -> us
-
-        let hw_params = HwParams::any(&pcm)?;
-        hw_params.set_access(Access::RWInterleaved)?;
-        // todo: add support for other formats
-        hw_params.set_format(Format::S16LE)?;
-        // todo: add support for other channels
-        hw_params.set_channels(2)?;
-        hw_params.set_rate_near(rate, ValueOr::Nearest)?;
-        hw_params.set_buffer_time_near(buffer_time, ValueOr::Nearest)?;
-        hw_params.set_period_time_near(period_time, ValueOr::Nearest)?;
-        pcm.hw_params(&hw_params)?;
-        drop(hw_params);
-
-        let (buffer_size, period_size) = pcm.get_params()?;
+    fn new(settings: &Settings, config: &AudioConfig) -> Result<ALSADriverPrev, super::Error> {
+        let (pcm, stream) = open(settings, Direction::Playback, config)?;
+        let StreamFormat {
+            frequency,
+            channels,
+            format,
+            ..
+        } = stream;
+        let buffer_size = stream.buffer_size as u64;
+        let period_size = stream.period_size as u64;
+        let latency = latency_ms(buffer_size, frequency);
 
         let sw_params = pcm.sw_params_current()?;
         sw_params.set_start_threshold(buffer_size as Frames / 2)?;
+        // status timestamps on the clock `Instant` uses, older plugins only
+        // have wall clock ones and the clock falls back to the call time
+        let monotonic = sw_params.set_tstamp_mode(true).is_ok()
+            && sw_params.set_tstamp_type(TstampType::Monotonic).is_ok();
         pcm.sw_params(&sw_params)?;
         drop(sw_params);
 
+        let period = period_size as usize * channels as usize;
+        // room for the period being written plus the one being filled
+        let (producer, consumer) = ring_buffer(period * 2);
+
         Ok(ALSADriverPrev {
-            blocking,
-            _buffer_size: buffer_size,
+            blocking: settings.blocking,
+            buffer: vec![0.0; period],
+            buffer_size,
+            bytes: vec![0; period * format.bytes()],
+            channels,
+            consumer,
+            format,
             frequency,
             latency,
-            buffer: Vec::with_capacity(period_size as usize * 2),
-            name: name.to_string(),
-            pcm,
+            monotonic,
+            name: settings.device.clone(),
+            pcm: Some(pcm),
             period_size,
+            producer,
+            stats: AudioStats::default(),
+            written: 0,
         })
     }
 
-    fn write(&mut self) -> Result<(), super::Error> {
-        loop {
-            let available = match self.pcm.avail_update() {
+    // `blocking` waits for room for everything in the ring that's ready
+    fn write(&mut self, blocking: bool) -> Result<(), super::Error> {
+        let pcm = self.pcm.as_ref().ok_or_else(closed)?;
+        let channels = self.channels as usize;
+        let count = self.consumer.peek_slice(&mut self.buffer);
+        let frames = (count / channels) as Frames;
+
+        // without blocking only what the device has room for goes out
+        let frames = loop {
+            let available = match pcm.avail_update() {
                 Ok(it) => it,
                 Err(err) => {
-                    self.pcm.recover(err.errno() as i32, true)?;
+                    recover(pcm, &mut self.stats.underruns, err)?;
                     continue;
                 }
             };
 
-            if available < self.buffer.len() as Frames {
-                if let Err(err) = self.pcm.wait(None) {
-                    self.pcm.recover(err.errno() as i32, true)?;
-                }
+            if available >= frames || !blocking {
+                break frames.min(available.max(0));
             }
 
-            if available >= self.buffer.len() as Frames {
-                break;
+            let start = Instant::now();
+            let waited = pcm.wait(None);
+            self.stats.blocking_time += start.elapsed();
+            if let Err(err) = waited {
+                recover(pcm, &mut self.stats.underruns, err)?;
             }
+        };
+        if frames == 0 {
+            return Ok(());
         }
+        let count = frames as usize * channels;
+
+        let start = Instant::now();
 
-        let mut output = self.buffer.as_slice();
+        let frame_bytes = self.format.bytes() * channels;
+        let bytes = &mut self.bytes[..count * self.format.bytes()];
+        sample::write_samples(self.format, &self.buffer[..count], bytes);
+
+        let mut output = &*bytes;
 
         let mut i = 4;
-        while output.len() > 0 && i >= 0 {
+        while !output.is_empty() && i >= 0 {
             i -= 1;
 
-            let io_i16 = self.pcm.io_i16()?;
+            let io = pcm.io_bytes();
 
-            match io_i16.writei(output) {
+            match io.writei(output) {
                 Ok(written) => {
-                    if written * 2 <= output.len() {
-                        output = &output[written as usize * 2..];
+                    if written * frame_bytes <= output.len() {
+                        output = &output[written * frame_bytes..];
                     }
                 }
+                // the device filled up since avail_update
+                Err(err) if !blocking && err.errno() as i32 == libc::EAGAIN => break,
                 Err(err) => {
-                    //no samples written
-                    if let Err(err) = self.pcm.recover(err.errno() as i32, true) {
-                        eprintln!("ALSA: {}", err);
-                    }
+                    //no samples written, a vanished device can't be recovered
+                    recover(pcm, &mut self.stats.underruns, err)?;
                 }
             }
         }
 
-        if i < 0 {
-            let (r, s, remain) = if output.len() == self.buffer.len() {
-                (2.., 0, self.buffer.len() - 2)
-            } else {
-                (self.buffer.len() - output.len().., 0, output.len())
-            };
-
-            self.buffer.copy_within(r, s);
-            self.buffer.truncate(remain);
+        let written = (count * self.format.bytes() - output.len()) / frame_bytes * channels;
+        self.written += (written / channels) as u64;
+        if i < 0 && written == 0 {
+            // drop the oldest frame so the buffer can't stall forever
+            self.consumer.consume(channels);
+            self.stats.dropped_frames += 1;
         } else {
-            let remain = output.len();
-            self.buffer.truncate(remain);
+            self.consumer.consume(written);
         }
 
+        self.stats.max_write_time = self.stats.max_write_time.max(start.elapsed());
         Ok(())
     }
+
+    fn queue(&mut self, mut samples: &[f32]) -> Result<(), super::Error> {
+        let channels = self.channels as usize;
+        let period = self.period_size as usize * channels;
+
+        loop {
+            let count = self.producer.push_slice(samples);
+            samples = &samples[count..];
+
+            let mut stalled = false;
+            while self.consumer.len() >= period && !stalled {
+                let queued = self.consumer.len();
+                self.write(self.blocking)?;
+                // only a non-blocking pcm comes back without writing
+                stalled = self.consumer.len() == queued;
+            }
+
+            if samples.is_empty() {
+                return Ok(());
+            }
+            // the device is full, what doesn't fit is dropped rather than
+            // waited for
+            if stalled {
+                self.stats.dropped_frames += (samples.len() / channels) as u64;
+                return Ok(());
+            }
+        }
+    }
+
+    fn drain(&mut self) -> Result<(), super::Error> {
+        while !self.consumer.is_empty() {
+            self.write(true)?;
+        }
+
+        // a non-blocking pcm returns right away and drains in the background
+        let pcm = self.pcm.as_ref().ok_or_else(closed)?;
+        if let Err(err) = pcm.drain() {
+            if pcm.state() != State::Draining {
+                return Err(err.into());
+            }
+        }
+        while pcm.state() == State::Draining {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        pcm.prepare()?;
+        Ok(())
+    }
+
+    // frames the listener has heard
+    fn played(&self) -> u64 {
+        let delay = self.pcm.as_ref().map_or(Ok(0), |pcm| pcm.delay());
+        self.written
+            .saturating_sub(delay.unwrap_or(0).max(0) as u64)
+    }
+
+    fn flush(&mut self) -> Result<(), super::Error> {
+        // the dropped frames never reach the listener, keep the clock honest
+        self.written = self.played();
+
+        self.consumer.clear();
+        let pcm = self.pcm.as_ref().ok_or_else(closed)?;
+        pcm.drop()?;
+        pcm.prepare()?;
+        Ok(())
+    }
+
+    // closes the device, what it still held is lost but the ring keeps its
+    // samples
+    fn release(&mut self) {
+        self.written = self.played();
+        self.pcm = None;
+    }
+
+    fn settings(&self, format: Option<SampleFormat>) -> Settings {
+        Settings {
+            device: self.name.clone(),
+            frequency: self.frequency,
+            channels: self.channels,
+            format,
+            latency: self.latency,
+            blocking: self.blocking,
+        }
+    }
 }
 
 pub struct ALSADriver {
-    device_names: Vec<String>,
+    devices: Vec<DeviceInfo>,
+    // the format asked for, `None` picks the first of `FORMATS` the device has
+    format: Option<SampleFormat>,
+    paused: bool,
     prev: ALSADriverPrev,
 }
 
+// pcms for `direction` without probing them, the first one stands in as
+// default when there is no "default" pcm
+fn enumerate(direction: Direction) -> Result<Vec<DeviceInfo>, Error> {
+    let mut devices = HintIter::new(None, &CString::new("pcm").unwrap())?
+        .filter(|hint| hint.direction.is_none_or(|it| it == direction))
+        .filter_map(|hint| {
+            let id = hint.name?;
+            // the first line names the card, the rest tells hw/plughw/... apart
+            let desc = hint.desc.unwrap_or_default();
+            let mut lines = desc.lines();
+            let name = lines.next().unwrap_or(&id).to_string();
+            let description = lines.collect::<Vec<_>>().join(" ");
+
+            Some(DeviceInfo {
+                is_default: id == "default",
+                id,
+                name,
+                description,
+                capabilities: DeviceCapabilities::default(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if !devices.iter().any(|device| device.is_default) {
+        if let Some(device) = devices.first_mut() {
+            device.is_default = true;
+        }
+    }
+    Ok(devices)
+}
+
 impl ALSADriver {
     pub fn new() -> Result<ALSADriver, super::Error> {
-        let device_names = HintIter::new(None, &CString::new("pcm").unwrap())?
-            .map(|hint| hint.name.unwrap().clone())
-            .collect::<Vec<_>>();
+        let devices = enumerate(Direction::Playback)?;
+        let default = devices
+            .iter()
+            .find(|device| device.is_default)
+            .ok_or_else(|| super::Error::new(ErrorKind::DeviceLost, "No playback device"))?;
+        let settings = Settings {
+            device: default.id.clone(),
+            frequency: 44100,
+            channels: 2,
+            format: None,
+            latency: 20,
+            blocking: false,
+        };
+        let prev = ALSADriverPrev::new(&settings, &AudioConfig::new())?;
 
-        if device_names.len() == 0 {
-            return Err(super::Error::NoDevice);
+        Ok(ALSADriver {
+            devices,
+            format: None,
+            paused: false,
+            prev,
+        })
+    }
+
+    // swaps in a new stream, the clock goes on from what the listener heard
+    // on the old one, converted in case the rate changed
+    fn replace(&mut self, mut prev: ALSADriverPrev) {
+        let played = self.prev.played() as u128;
+        prev.written = (played * prev.frequency as u128 / self.prev.frequency as u128) as u64;
+        self.prev = prev;
+    }
+
+    // brings back the stream `configure` let go of, if that fails as well it
+    // stays closed and the next write reports the device lost
+    fn restore(&mut self, released: bool, err: super::Error) -> super::Error {
+        if released {
+            let name = self.prev.name.clone();
+            let _ = self.reopen(Some(&name));
         }
+        err
+    }
+}
+
+// `Instant` counts on CLOCK_MONOTONIC too, so a status timestamp lands on it
+// by its distance from now
+fn instant(timestamp: libc::timespec) -> Option<Instant> {
+    if timestamp.tv_sec == 0 && timestamp.tv_nsec == 0 {
+        return None;
+    }
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
 
-        let prev = ALSADriverPrev::new(&device_names[0], 20, 44100, false)?;
+    let nanos = |time: libc::timespec| time.tv_sec as i128 * 1_000_000_000 + time.tv_nsec as i128;
+    let age = nanos(now) - nanos(timestamp);
+    let instant = Instant::now();
+    if age >= 0 {
+        instant.checked_sub(Duration::from_nanos(age as u64))
+    } else {
+        instant.checked_add(Duration::from_nanos(-age as u64))
+    }
+}
 
-        Ok(ALSADriver { device_names, prev })
+fn add_watch(fd: libc::c_int, path: &str, mask: u32) -> io::Result<libc::c_int> {
+    let path = CString::new(path).unwrap();
+    let watch = unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) };
+    if watch < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(watch)
+}
+
+// Wakes up when nodes come and go in /dev/snd, which is where a card shows up
+// once udev has set it up. A machine without a card has no /dev/snd yet, /dev
+// is watched for it until it appears.
+pub struct ALSADeviceSource {
+    // the watch on /dev, only while there is no /dev/snd
+    dev: Option<libc::c_int>,
+    inotify: OwnedFd,
+}
+
+impl ALSADeviceSource {
+    pub fn new() -> Result<ALSADeviceSource, super::Error> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(super::Error::with_source(
+                ErrorKind::Backend,
+                io::Error::last_os_error(),
+            ));
+        }
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut source = ALSADeviceSource { dev: None, inotify };
+        source
+            .watch()
+            .map_err(|err| super::Error::with_source(ErrorKind::Backend, err))?;
+        Ok(source)
+    }
+
+    // watches /dev/snd if it is there and /dev otherwise, /dev goes first so
+    // /dev/snd can't show up unnoticed in between
+    fn watch(&mut self) -> io::Result<()> {
+        let fd = self.inotify.as_raw_fd();
+        if self.dev.is_none() {
+            self.dev = Some(add_watch(fd, "/dev", libc::IN_CREATE)?);
+        }
+
+        match add_watch(fd, "/dev/snd", libc::IN_CREATE | libc::IN_DELETE) {
+            Ok(_) => {
+                if let Some(dev) = self.dev.take() {
+                    unsafe { libc::inotify_rm_watch(fd, dev) };
+                }
+                Ok(())
+            }
+            Err(err) if err.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl DeviceSource for ALSADeviceSource {
+    fn devices(&mut self) -> Result<Vec<DeviceInfo>, super::Error> {
+        Ok(enumerate(Direction::Playback)?)
+    }
+
+    fn wait(&mut self, timeout: Duration) -> bool {
+        let fd = self.inotify.as_raw_fd();
+        let mut poll_fd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+        if unsafe { libc::poll(&mut poll_fd, 1, timeout) } <= 0 {
+            return false;
+        }
+
+        // a card creates its nodes one after another, let them all arrive
+        thread::sleep(Duration::from_millis(100));
+        let mut events = [0u8; 4096];
+        while unsafe { libc::read(fd, events.as_mut_ptr().cast(), events.len()) } > 0 {}
+
+        // /dev/snd came with the first card or went with the last one
+        let waiting = self.dev.is_some();
+        if waiting || !Path::new("/dev/snd").exists() {
+            let _ = self.watch();
+            // anything else in /dev doesn't matter
+            if waiting && self.dev.is_some() {
+                return false;
+            }
+        }
+        true
     }
 }
 
@@ -153,8 +763,13 @@ impl AudioDriver for ALSADriver {
         "ALSA"
     }
 
-    fn support_device_list(&self) -> Vec<String> {
-        self.device_names.clone()
+    fn support_device_list(&self) -> Vec<DeviceInfo> {
+        probe_devices(
+            &self.devices,
+            Direction::Playback,
+            &self.prev.name,
+            self.prev.pcm.as_ref(),
+        )
     }
 
     fn support_blocking(&self) -> bool {
@@ -162,112 +777,478 @@ impl AudioDriver for ALSADriver {
     }
 
     fn support_channels(&self) -> Vec<u32> {
-        vec![2]
+        let pcm = self.prev.pcm.as_ref();
+        pcm.and_then(|pcm| probe(pcm).ok())
+            .map(|capabilities| capabilities.channels)
+            .unwrap_or_default()
     }
 
     fn support_frequencies(&self) -> Vec<u32> {
-        vec![44100, 48000, 96000]
+        let pcm = self.prev.pcm.as_ref();
+        pcm.and_then(|pcm| probe(pcm).ok())
+            .map(|capabilities| capabilities.frequencies)
+            .unwrap_or_default()
     }
 
     fn support_latencies(&self) -> Vec<u32> {
-        vec![20, 40, 60, 80, 100]
+        LATENCIES
+            .into_iter()
+            .filter(|&latency| self.test_latency(latency))
+            .collect()
+    }
+
+    fn test_channels(&self, channels: u32) -> bool {
+        let config = AudioConfig::new().channels(channels);
+        let pcm = self.prev.pcm.as_ref();
+        pcm.is_some_and(|pcm| check(pcm, &config, self.prev.frequency).is_ok())
+    }
+
+    fn test_frequency(&self, frequency: u32) -> bool {
+        let config = AudioConfig::new().frequency(frequency);
+        let pcm = self.prev.pcm.as_ref();
+        pcm.is_some_and(|pcm| check(pcm, &config, frequency).is_ok())
+    }
+
+    fn test_latency(&self, latency: u32) -> bool {
+        let pcm = self.prev.pcm.as_ref();
+        pcm.and_then(|pcm| hw_params_any(pcm).ok())
+            .is_some_and(|hw_params| fits_latency(&hw_params, latency, self.prev.frequency))
     }
 
     fn set_device(&mut self, device: &str) -> Result<(), super::Error> {
-        if !self.device_names.contains(&device.to_string()) {
-            return Err(super::Error::DeviceNotFound(device.to_string()));
+        self.configure(&AudioConfig::new().device(device))?;
+        Ok(())
+    }
+
+    fn set_blocking(&mut self, blocking: bool) -> Result<(), super::Error> {
+        self.configure(&AudioConfig::new().blocking(blocking))?;
+        Ok(())
+    }
+
+    fn set_channels(&mut self, channels: u32) -> Result<(), super::Error> {
+        self.configure(&AudioConfig::new().channels(channels))?;
+        Ok(())
+    }
+
+    fn set_frequency(&mut self, frequency: u32) -> Result<(), super::Error> {
+        self.configure(&AudioConfig::new().frequency(frequency))?;
+        Ok(())
+    }
+
+    fn set_latency(&mut self, latency: u32) -> Result<(), super::Error> {
+        self.configure(&AudioConfig::new().latency(latency))?;
+        Ok(())
+    }
+
+    fn configure(&mut self, config: &AudioConfig) -> Result<AudioConfig, super::Error> {
+        let target = resolve(
+            config,
+            &self.devices,
+            self.prev.pcm.as_ref(),
+            self.prev.settings(self.format),
+            self.prev.format,
+        )?;
+        let Some(target) = target else {
+            return Ok(self.config());
+        };
+
+        // the old stream goes first when it holds the card, and comes back
+        // with its old settings if the new one doesn't open
+        let released = shares_card(&target.device, &self.prev.name);
+        if released {
+            self.prev.release();
         }
+        match ALSADriverPrev::new(&target, config) {
+            Ok(prev) => {
+                self.replace(prev);
+                self.format = target.format;
+                Ok(self.config())
+            }
+            Err(err) => Err(self.restore(released, err)),
+        }
+    }
 
-        if self.prev.name == device.to_string() {
-            return Ok(());
+    fn config(&self) -> AudioConfig {
+        AudioConfig {
+            device: Some(self.prev.name.clone()),
+            frequency: Some(self.prev.frequency),
+            channels: Some(self.prev.channels),
+            format: Some(self.prev.format),
+            latency: Some(self.prev.latency),
+            blocking: Some(self.prev.blocking),
+            exclusive: Some(false),
         }
+    }
 
-        self.prev = ALSADriverPrev::new(
-            device,
-            self.prev.latency,
-            self.prev.frequency,
-            self.prev.blocking,
-        )?;
-        Ok(())
+    fn current_format(&self) -> StreamFormat {
+        StreamFormat {
+            frequency: self.prev.frequency,
+            channels: self.prev.channels,
+            format: self.prev.format,
+            period_size: self.prev.period_size as usize,
+            buffer_size: self.prev.buffer_size as usize,
+        }
     }
 
-    fn set_blocking(&mut self, blocking: bool) -> Result<(), super::Error> {
-        if self.prev.blocking == blocking {
+    fn frequency(&self) -> u32 {
+        self.prev.frequency
+    }
+
+    fn channels(&self) -> u32 {
+        self.prev.channels
+    }
+
+    // alsa puts the rear pair before center and lfe
+    fn speakers(&self) -> Vec<Speaker> {
+        use Speaker::*;
+
+        match self.prev.channels {
+            4 => vec![FrontLeft, FrontRight, BackLeft, BackRight],
+            6 => vec![
+                FrontLeft,
+                FrontRight,
+                BackLeft,
+                BackRight,
+                FrontCenter,
+                LowFrequency,
+            ],
+            8 => vec![
+                FrontLeft,
+                FrontRight,
+                BackLeft,
+                BackRight,
+                FrontCenter,
+                LowFrequency,
+                SideLeft,
+                SideRight,
+            ],
+            channels => super::channel::default_speakers(channels),
+        }
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.prev.format
+    }
+
+    fn queued_frames(&self) -> usize {
+        let available = self.prev.pcm.as_ref().map(|pcm| pcm.avail_update());
+        let device = match available {
+            Some(Ok(available)) => (self.prev.buffer_size as Frames - available).max(0) as usize,
+            _ => 0,
+        };
+        device + self.prev.consumer.len() / self.prev.channels as usize
+    }
+
+    fn buffer_capacity_frames(&self) -> usize {
+        (self.prev.buffer_size + self.prev.period_size) as usize
+    }
+
+    fn estimated_latency(&self) -> Duration {
+        // delay also covers what sits in the hardware fifo past the buffer
+        let delay = match self.prev.pcm.as_ref().map(|pcm| pcm.delay()) {
+            Some(Ok(delay)) => delay.max(0) as usize,
+            _ => 0,
+        };
+        let frames = delay + self.prev.consumer.len() / self.prev.channels as usize;
+        Duration::from_secs_f64(frames as f64 / self.prev.frequency as f64)
+    }
+
+    fn clock(&self) -> Option<AudioClock> {
+        let status = self.prev.pcm.as_ref()?.status().ok()?;
+        let timestamp = self
+            .prev
+            .monotonic
+            .then(|| instant(status.get_htstamp()))
+            .flatten()
+            .unwrap_or_else(Instant::now);
+        let delay = status.get_delay().max(0) as u64;
+
+        Some(AudioClock {
+            position: self.prev.written.saturating_sub(delay),
+            frequency: self.prev.frequency,
+            timestamp,
+        })
+    }
+
+    fn pause(&mut self) -> Result<(), super::Error> {
+        if self.paused {
             return Ok(());
         }
 
-        self.prev = ALSADriverPrev::new(
-            &self.prev.name,
-            self.prev.latency,
-            self.prev.frequency,
-            blocking,
-        )?;
+        // without hardware pause the device just runs dry and recovers from
+        // the underrun on resume
+        let pcm = self.prev.pcm.as_ref().ok_or_else(closed)?;
+        if pcm.state() == State::Running && pcm.hw_params_current()?.can_pause() {
+            pcm.pause(true)?;
+        }
+        self.paused = true;
         Ok(())
     }
 
-    fn set_frequency(&mut self, frequency: u32) -> Result<(), super::Error> {
-        if !self.support_frequencies().contains(&frequency) {
-            return Err(super::Error::Unsupported(format!(
-                "frequency: {}",
-                frequency
-            )));
+    fn resume(&mut self) -> Result<(), super::Error> {
+        if !self.paused {
+            return Ok(());
         }
 
-        if self.prev.frequency == frequency {
-            return Ok(());
+        let pcm = self.prev.pcm.as_ref().ok_or_else(closed)?;
+        if pcm.state() == State::Paused {
+            pcm.pause(false)?;
         }
+        self.paused = false;
+        Ok(())
+    }
 
-        self.prev = ALSADriverPrev::new(
-            &self.prev.name,
-            self.prev.latency,
-            frequency,
-            self.prev.blocking,
-        )?;
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn drain(&mut self) -> Result<(), super::Error> {
+        self.resume()?;
+        self.prev.drain()
+    }
+
+    fn flush(&mut self) -> Result<(), super::Error> {
+        self.prev.flush()
+    }
+
+    fn refresh_devices(&mut self) -> Result<(), super::Error> {
+        self.devices = enumerate(Direction::Playback)?;
         Ok(())
     }
 
-    fn set_latency(&mut self, latency: u32) -> Result<(), super::Error> {
-        if !self.support_latencies().contains(&latency) {
-            return Err(super::Error::Unsupported(format!("latency: {}", latency)));
+    fn reopen(&mut self, id: Option<&str>) -> Result<(), super::Error> {
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => self
+                .devices
+                .iter()
+                .find(|device| device.is_default)
+                .map_or(self.prev.name.clone(), |device| device.id.clone()),
+        };
+
+        let mut pending = vec![0.0; self.prev.consumer.len()];
+        self.prev.consumer.pop_slice(&mut pending);
+
+        // the lost handle still holds the card until it is closed
+        if shares_card(&id, &self.prev.name) {
+            self.prev.release();
         }
+        let settings = Settings {
+            device: id,
+            ..self.prev.settings(self.format)
+        };
+        match ALSADriverPrev::new(&settings, &AudioConfig::new()) {
+            Ok(mut prev) => {
+                prev.stats = std::mem::take(&mut self.prev.stats);
+                // another device may have settled on other channels
+                if prev.channels != self.prev.channels {
+                    prev.stats.dropped_frames +=
+                        (pending.len() / self.prev.channels as usize) as u64;
+                    pending.clear();
+                }
+                self.replace(prev);
+                self.prev.producer.push_slice(&pending);
+                Ok(())
+            }
+            Err(err) => {
+                self.prev.producer.push_slice(&pending);
+                Err(err)
+            }
+        }
+    }
 
-        if self.prev.latency == latency {
+    fn output_frames(&mut self, samples: &[f32]) -> Result<(), super::Error> {
+        if self.paused {
+            // nothing plays, keep what fits until resume
+            let count = self.prev.producer.push_slice(samples);
+            let channels = self.prev.channels as usize;
+            self.prev.stats.dropped_frames += ((samples.len() - count) / channels) as u64;
             return Ok(());
         }
 
-        self.prev = ALSADriverPrev::new(
-            &self.prev.name,
-            latency,
-            self.prev.frequency,
-            self.prev.blocking,
-        )?;
-        Ok(())
+        self.prev.queue(samples)
+    }
+
+    fn take_stats(&mut self) -> AudioStats {
+        std::mem::take(&mut self.prev.stats)
+    }
+}
+
+// the capture stream starts with the first read, like `readi` would on its own
+fn open_input(
+    settings: &Settings,
+    config: &AudioConfig,
+) -> Result<(PCM, StreamFormat), super::Error> {
+    let (pcm, stream) = open(settings, Direction::Capture, config)?;
+
+    let sw_params = pcm.sw_params_current()?;
+    sw_params.set_start_threshold(1)?;
+    pcm.sw_params(&sw_params)?;
+    drop(sw_params);
+
+    Ok((pcm, stream))
+}
+
+// Reads straight from the device, there is nothing to smooth out on this side
+// so it needs no ring.
+pub struct ALSAInputDriver {
+    blocking: bool,
+    bytes: Vec<u8>,
+    devices: Vec<DeviceInfo>,
+    // the format asked for, `None` picks the first of `FORMATS` the device has
+    format: Option<SampleFormat>,
+    latency: u32,
+    name: String,
+    // `None` while `configure` has let go of the card for another pcm on it
+    pcm: Option<PCM>,
+    stats: AudioStats,
+    stream: StreamFormat,
+}
+
+impl ALSAInputDriver {
+    pub fn new() -> Result<ALSAInputDriver, super::Error> {
+        let devices = enumerate(Direction::Capture)?;
+        let default = devices
+            .iter()
+            .find(|device| device.is_default)
+            .ok_or_else(|| super::Error::new(ErrorKind::DeviceLost, "No capture device"))?;
+        let settings = Settings {
+            device: default.id.clone(),
+            frequency: 44100,
+            channels: 1,
+            format: None,
+            latency: 20,
+            blocking: false,
+        };
+        let (pcm, stream) = open_input(&settings, &AudioConfig::new())?;
+
+        Ok(ALSAInputDriver {
+            blocking: false,
+            bytes: Vec::new(),
+            devices,
+            format: None,
+            latency: latency_ms(stream.buffer_size as u64, stream.frequency),
+            name: settings.device,
+            pcm: Some(pcm),
+            stats: AudioStats::default(),
+            stream,
+        })
+    }
+}
+
+impl AudioInputDriver for ALSAInputDriver {
+    fn driver(&self) -> &'static str {
+        "ALSA"
+    }
+
+    fn support_device_list(&self) -> Vec<DeviceInfo> {
+        probe_devices(
+            &self.devices,
+            Direction::Capture,
+            &self.name,
+            self.pcm.as_ref(),
+        )
     }
 
-    fn output(&mut self, samples: &[f64]) -> Result<(), super::Error> {
-        self.prev.buffer.push((samples[0] * 32767.0) as i16);
-        self.prev.buffer.push((samples[1] * 32767.0) as i16);
+    fn configure(&mut self, config: &AudioConfig) -> Result<AudioConfig, super::Error> {
+        let current = Settings {
+            device: self.name.clone(),
+            frequency: self.stream.frequency,
+            channels: self.stream.channels,
+            format: self.format,
+            latency: self.latency,
+            blocking: self.blocking,
+        };
+        let target = resolve(
+            config,
+            &self.devices,
+            self.pcm.as_ref(),
+            current.clone(),
+            self.stream.format,
+        )?;
+        let Some(target) = target else {
+            return Ok(self.config());
+        };
 
-        println!(
-            "{} {}",
-            self.prev.buffer.len(),
-            self.prev.period_size as usize * 2
-        );
-        if self.prev.buffer.len() >= self.prev.period_size as usize * 2 {
-            self.prev.write()?;
+        // like playback, the old stream goes first when it holds the card
+        let released = shares_card(&target.device, &self.name);
+        if released {
+            self.pcm = None;
         }
+        let (pcm, stream) = match open_input(&target, config) {
+            Ok(it) => it,
+            Err(err) => {
+                if released {
+                    self.pcm = open_input(&current, &AudioConfig::new())
+                        .ok()
+                        .map(|(pcm, _)| pcm);
+                }
+                return Err(err);
+            }
+        };
+        self.blocking = target.blocking;
+        self.format = target.format;
+        self.latency = latency_ms(stream.buffer_size as u64, stream.frequency);
+        self.name = target.device;
+        self.pcm = Some(pcm);
+        self.stream = stream;
+        Ok(self.config())
+    }
 
-        Ok(())
+    fn config(&self) -> AudioConfig {
+        AudioConfig {
+            device: Some(self.name.clone()),
+            frequency: Some(self.stream.frequency),
+            channels: Some(self.stream.channels),
+            format: Some(self.stream.format),
+            latency: Some(self.latency),
+            blocking: Some(self.blocking),
+            exclusive: Some(false),
+        }
     }
 
-    fn output_i16(&mut self, samples: &[i16]) -> Result<(), super::Error> {
-        self.prev.buffer.push(samples[0]);
-        self.prev.buffer.push(samples[1]);
+    fn current_format(&self) -> StreamFormat {
+        self.stream
+    }
 
-        if self.prev.buffer.len() >= self.prev.period_size as usize * 2 {
-            self.prev.write()?;
+    fn available_frames(&self) -> usize {
+        let available = self.pcm.as_ref().map(|pcm| pcm.avail_update());
+        match available {
+            Some(Ok(available)) => available.max(0) as usize,
+            _ => 0,
         }
+    }
 
-        Ok(())
+    fn read_frames(&mut self, samples: &mut [f32]) -> Result<usize, super::Error> {
+        let channels = self.stream.channels as usize;
+        let frame_bytes = self.stream.format.bytes() * channels;
+        self.bytes.resize(samples.len() / channels * frame_bytes, 0);
+
+        let pcm = self.pcm.as_ref().ok_or_else(closed)?;
+        let start = Instant::now();
+        let mut read = 0;
+        for _ in 0..4 {
+            match pcm.io_bytes().readi(&mut self.bytes) {
+                Ok(frames) => {
+                    read = frames;
+                    break;
+                }
+                // nothing captured yet on a non-blocking pcm
+                Err(err) if err.errno() as i32 == libc::EAGAIN => break,
+                Err(err) => recover(pcm, &mut self.stats.overruns, err)?,
+            }
+        }
+        if self.blocking {
+            self.stats.blocking_time += start.elapsed();
+        }
+
+        let count = read * channels;
+        let bytes = &self.bytes[..read * frame_bytes];
+        sample::read_samples(self.stream.format, bytes, &mut samples[..count]);
+        Ok(read)
+    }
+
+    fn take_stats(&mut self) -> AudioStats {
+        std::mem::take(&mut self.stats)
     }
 }
@@ -0,0 +1,239 @@
+use std::f64::consts::PI;
+
+use super::Dsp;
+
+// Response shapes from the Audio EQ Cookbook. `gain_db` only matters for
+// the peak and shelf filters, `q` not for the first order ones, which model
+// a single RC stage like the output circuits of most consoles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BiquadKind {
+    LowPass,
+    HighPass,
+    FirstOrderLowPass,
+    FirstOrderHighPass,
+    BandPass,
+    Notch,
+    Peak,
+    LowShelf,
+    HighShelf,
+}
+
+// Second order IIR filter, one state per channel. A `q` of 1/√2 gives the
+// flattest pass band for the low- and high-pass.
+pub struct Biquad {
+    // b0, b1, b2, a1, a2, normalized by a0
+    coefficients: [f64; 5],
+    frequency: f32,
+    gain_db: f32,
+    kind: BiquadKind,
+    q: f32,
+    rate: u32,
+    // transposed direct form II, two delays per channel
+    state: Vec<[f64; 2]>,
+}
+
+impl Biquad {
+    pub fn new(kind: BiquadKind, frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        Biquad {
+            coefficients: [1.0, 0.0, 0.0, 0.0, 0.0],
+            frequency,
+            gain_db,
+            kind,
+            q,
+            rate: 0,
+            state: Vec::new(),
+        }
+    }
+
+    pub fn low_pass(frequency: f32, q: f32) -> Biquad {
+        Biquad::new(BiquadKind::LowPass, frequency, q, 0.0)
+    }
+
+    pub fn high_pass(frequency: f32, q: f32) -> Biquad {
+        Biquad::new(BiquadKind::HighPass, frequency, q, 0.0)
+    }
+
+    pub fn peak(frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        Biquad::new(BiquadKind::Peak, frequency, q, gain_db)
+    }
+
+    pub fn kind(&self) -> BiquadKind {
+        self.kind
+    }
+
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    pub fn q(&self) -> f32 {
+        self.q
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    // retunes the filter, the state is kept so a sweep doesn't click
+    pub fn set(&mut self, frequency: f32, q: f32, gain_db: f32) {
+        self.frequency = frequency;
+        self.q = q;
+        self.gain_db = gain_db;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        if self.rate == 0 {
+            return;
+        }
+
+        let rate = self.rate as f64;
+        // stay clear of nyquist, the cookbook formulas fall apart there
+        let frequency = (self.frequency as f64).clamp(1.0, rate * 0.49);
+        let omega = 2.0 * PI * frequency / rate;
+        let (sin, cos) = omega.sin_cos();
+        let alpha = sin / (2.0 * (self.q as f64).max(1e-3));
+        let a = 10f64.powf(self.gain_db as f64 / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+        // bilinear transform of an RC stage, prewarped to hit the cutoff
+        let k = (PI * frequency / rate).tan();
+
+        let [b0, b1, b2, a0, a1, a2] = match self.kind {
+            BiquadKind::LowPass => [
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ],
+            BiquadKind::HighPass => [
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ],
+            BiquadKind::FirstOrderLowPass => [k, k, 0.0, 1.0 + k, k - 1.0, 0.0],
+            BiquadKind::FirstOrderHighPass => [1.0, -1.0, 0.0, 1.0 + k, k - 1.0, 0.0],
+            BiquadKind::BandPass => [alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            BiquadKind::Notch => [1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            BiquadKind::Peak => [
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ],
+            BiquadKind::LowShelf => [
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ],
+            BiquadKind::HighShelf => [
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ],
+        };
+
+        self.coefficients = [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0];
+    }
+}
+
+impl Dsp for Biquad {
+    fn prepare(&mut self, frequency: u32, channels: usize) {
+        self.rate = frequency;
+        self.state.resize(channels, [0.0; 2]);
+        self.update();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.state.len().max(1);
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+
+        for frame in samples.chunks_exact_mut(channels) {
+            for (sample, [z1, z2]) in frame.iter_mut().zip(&mut self.state) {
+                let input = *sample as f64;
+                let output = b0 * input + *z1;
+                *z1 = b1 * input - a1 * output + *z2;
+                *z2 = b2 * input - a2 * output;
+                *sample = output as f32;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state.fill([0.0; 2]);
+    }
+}
+
+// Peak and shelf bands in series, each one a `Biquad`.
+#[derive(Default)]
+pub struct ParametricEq {
+    bands: Vec<Biquad>,
+    channels: usize,
+    rate: u32,
+}
+
+impl ParametricEq {
+    pub fn new() -> ParametricEq {
+        ParametricEq::default()
+    }
+
+    pub fn band(self, frequency: f32, q: f32, gain_db: f32) -> ParametricEq {
+        self.with(Biquad::peak(frequency, q, gain_db))
+    }
+
+    pub fn low_shelf(self, frequency: f32, gain_db: f32) -> ParametricEq {
+        let q = std::f32::consts::FRAC_1_SQRT_2;
+        self.with(Biquad::new(BiquadKind::LowShelf, frequency, q, gain_db))
+    }
+
+    pub fn high_shelf(self, frequency: f32, gain_db: f32) -> ParametricEq {
+        let q = std::f32::consts::FRAC_1_SQRT_2;
+        self.with(Biquad::new(BiquadKind::HighShelf, frequency, q, gain_db))
+    }
+
+    fn with(mut self, mut band: Biquad) -> ParametricEq {
+        if self.rate != 0 {
+            band.prepare(self.rate, self.channels);
+        }
+        self.bands.push(band);
+        self
+    }
+
+    // bands in the order they were added, retune them with `Biquad::set`
+    pub fn bands_mut(&mut self) -> &mut [Biquad] {
+        &mut self.bands
+    }
+}
+
+impl Dsp for ParametricEq {
+    fn prepare(&mut self, frequency: u32, channels: usize) {
+        self.rate = frequency;
+        self.channels = channels;
+        for band in &mut self.bands {
+            band.prepare(frequency, channels);
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for band in &mut self.bands {
+            band.process(samples);
+        }
+    }
+
+    fn reset(&mut self) {
+        for band in &mut self.bands {
+            band.reset();
+        }
+    }
+}
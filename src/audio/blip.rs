@@ -0,0 +1,215 @@
+use super::resampler::{blackman, sinc};
+
+// taps of the band-limited step, the output lags the clock by half of them
+const WIDTH: usize = 16;
+
+// step positions between two output samples, interpolated linearly
+const PHASE_BITS: u32 = 5;
+const PHASES: usize = 1 << PHASE_BITS;
+
+// fixed point fraction of clock-to-sample positions
+const TIME_BITS: u32 = 32;
+
+// kernel rows sum to 1 << DELTA_BITS, a delta of 1 moves the output by 1
+const DELTA_BITS: u32 = 15;
+
+// strength of the high-pass the integrator leaks through, about 15 Hz at
+// 48 kHz, which keeps the output from drifting like a real DAC's coupling cap
+const BASS_SHIFT: u32 = 9;
+
+// passband edge relative to nyquist
+const CUTOFF: f64 = 0.9;
+
+// Band-limited synthesis for sound chips, in the style of blip_buf. A core
+// records amplitude changes at clock times with `add_delta`, closes each
+// frame with `end_frame` and reads 16-bit samples at the output rate, which
+// go to `Audio::output_frames` as they are. Steps are drawn with a windowed
+// sinc, so square and pulse waves don't alias.
+pub struct BlipBuffer {
+    // sums of kernel-shaped deltas, integrated when read
+    buffer: Vec<i64>,
+    // output samples per clock, fixed point
+    factor: u64,
+    integrator: i64,
+    kernel: Vec<i32>,
+    // end of the last frame, fixed point samples from buffer[0]
+    offset: u64,
+    size: usize,
+}
+
+impl BlipBuffer {
+    // `size` is the most samples a frame may produce before they are read
+    pub fn new(size: usize) -> BlipBuffer {
+        let mut buffer = BlipBuffer {
+            buffer: vec![0; size + WIDTH],
+            factor: 1 << TIME_BITS,
+            integrator: 0,
+            kernel: kernel(),
+            offset: 0,
+            size,
+        };
+        buffer.clear();
+        buffer
+    }
+
+    // may change between frames, e.g. when a core switches its clock divider
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        let factor = (sample_rate / clock_rate * (1u64 << TIME_BITS) as f64).ceil();
+        self.factor = (factor as u64).max(1);
+    }
+
+    // call it after the first `set_rates`, before any delta is added
+    pub fn clear(&mut self) {
+        // start half a sample in so steps on a sample boundary are centered
+        self.offset = self.factor / 2;
+        self.integrator = 0;
+        self.buffer.fill(0);
+    }
+
+    // clocks to run to have at least `samples` samples available
+    pub fn clocks_needed(&self, samples: usize) -> u32 {
+        let needed = (samples as u64) << TIME_BITS;
+        if needed < self.offset {
+            return 0;
+        }
+        ((needed - self.offset).div_ceil(self.factor)) as u32
+    }
+
+    // the amplitude changes by `delta` at clock `time` of the current frame
+    pub fn add_delta(&mut self, time: u32, delta: i32) {
+        let fixed = time as u64 * self.factor + self.offset;
+        let index = (fixed >> TIME_BITS) as usize;
+        let phase = ((fixed >> (TIME_BITS - PHASE_BITS)) as usize) & (PHASES - 1);
+        let interp_bits = TIME_BITS - PHASE_BITS - DELTA_BITS;
+        let interp = ((fixed >> interp_bits) & ((1 << DELTA_BITS) - 1)) as i64;
+
+        let current = &self.kernel[phase * WIDTH..(phase + 1) * WIDTH];
+        let next = &self.kernel[(phase + 1) * WIDTH..(phase + 2) * WIDTH];
+        let output = &mut self.buffer[index..index + WIDTH];
+        // rounded, truncating would make every step fall short a little
+        for (output, (&current, &next)) in output.iter_mut().zip(current.iter().zip(next)) {
+            let weight = current as i64 * ((1 << DELTA_BITS) - interp)
+                + next as i64 * interp
+                + (1 << (DELTA_BITS - 1));
+            *output += (weight >> DELTA_BITS) * delta as i64;
+        }
+    }
+
+    // like `add_delta` without interpolating between phases, for chips
+    // whose changes are too frequent for the difference to be heard
+    pub fn add_delta_fast(&mut self, time: u32, delta: i32) {
+        let fixed = time as u64 * self.factor + self.offset;
+        let index = (fixed >> TIME_BITS) as usize;
+        let phase = ((fixed >> (TIME_BITS - PHASE_BITS)) as usize) & (PHASES - 1);
+
+        let kernel = &self.kernel[phase * WIDTH..(phase + 1) * WIDTH];
+        let output = &mut self.buffer[index..index + WIDTH];
+        for (output, &weight) in output.iter_mut().zip(kernel) {
+            *output += weight as i64 * delta as i64;
+        }
+    }
+
+    // closes the frame after `clock_duration` clocks, the next frame's times
+    // start from 0 again and its samples become readable
+    pub fn end_frame(&mut self, clock_duration: u32) {
+        self.offset += clock_duration as u64 * self.factor;
+        assert!(
+            self.samples_avail() <= self.size,
+            "BlipBuffer: frame is longer than the buffer"
+        );
+    }
+
+    pub fn samples_avail(&self) -> usize {
+        (self.offset >> TIME_BITS) as usize
+    }
+
+    // reads up to `output.len()` samples, or every other slot with `stereo`
+    // so two buffers can fill the channels of one interleaved block, returns
+    // how many samples were read
+    pub fn read_samples(&mut self, output: &mut [i16], stereo: bool) -> usize {
+        let step = if stereo { 2 } else { 1 };
+        let count = output.len().div_ceil(step).min(self.samples_avail());
+
+        let mut sum = self.integrator;
+        for (output, &delta) in output.iter_mut().step_by(step).zip(&self.buffer[..count]) {
+            let sample = (sum >> DELTA_BITS).clamp(i16::MIN as i64, i16::MAX as i64);
+            *output = sample as i16;
+            sum += delta - (sample << (DELTA_BITS - BASS_SHIFT));
+        }
+        self.integrator = sum;
+
+        self.offset -= (count as u64) << TIME_BITS;
+        self.buffer.copy_within(count.., 0);
+        let length = self.buffer.len();
+        self.buffer[length - count..].fill(0);
+        count
+    }
+}
+
+// the band-limited impulse for each phase, the integrator turns it into a step
+fn kernel() -> Vec<i32> {
+    let half = WIDTH / 2;
+    let mut kernel = Vec::with_capacity((PHASES + 1) * WIDTH);
+
+    for phase in 0..=PHASES {
+        let fraction = phase as f64 / PHASES as f64;
+        let row = (0..WIDTH)
+            .map(|tap| {
+                let x = tap as f64 - (half - 1) as f64 - fraction;
+                CUTOFF * sinc(CUTOFF * x) * blackman(x / half as f64)
+            })
+            .collect::<Vec<_>>();
+        let sum = row.iter().sum::<f64>();
+        let scale = (1 << DELTA_BITS) as f64 / sum;
+        let mut row = row
+            .iter()
+            .map(|&weight| (weight * scale).round() as i32)
+            .collect::<Vec<_>>();
+
+        // rounding must not leave a residue, the integrator would drift on it
+        let error = (1 << DELTA_BITS) - row.iter().sum::<i32>();
+        row[half - 1 + (phase * 2 + PHASES) / (2 * PHASES)] += error;
+        kernel.extend(row);
+    }
+
+    kernel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reads a frame of `clocks` with a step at its start, returns the level
+    // the output ended on with what the high-pass leaked added back
+    fn step(buffer: &mut BlipBuffer, leaked: &mut i64, clocks: u32, delta: i32) -> f64 {
+        buffer.add_delta(clocks / 10, delta);
+        buffer.end_frame(clocks);
+
+        let mut output = vec![0; buffer.samples_avail()];
+        let count = buffer.read_samples(&mut output, false);
+        let mut level = 0.0;
+        for &sample in &output[..count] {
+            level = sample as f64 + *leaked as f64 / (1 << BASS_SHIFT) as f64;
+            *leaked += sample as i64;
+        }
+        level
+    }
+
+    #[test]
+    fn step_settles_at_its_delta() {
+        let mut buffer = BlipBuffer::new(4800);
+        buffer.set_rates(1789773.0, 48000.0);
+        buffer.clear();
+        let mut leaked = 0;
+
+        let level = step(&mut buffer, &mut leaked, 17897, 1000);
+        assert!((level - 1000.0).abs() < 1.0, "settled at {}", level);
+
+        // the position carries over into the new rate
+        buffer.set_rates(3579545.0, 44100.0);
+        let level = step(&mut buffer, &mut leaked, 35795, 0);
+        assert!((level - 1000.0).abs() < 1.0, "settled at {}", level);
+        let level = step(&mut buffer, &mut leaked, 35795, 1000);
+        assert!((level - 2000.0).abs() < 1.0, "settled at {}", level);
+    }
+}
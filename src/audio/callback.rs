@@ -0,0 +1,203 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[cfg(target_os = "windows")]
+use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED};
+
+use super::{Audio, AudioDriverType, Error, ErrorKind};
+
+// frames handed to the callback per pull, as a fraction of a second
+const BLOCKS_PER_SECOND: u32 = 100;
+
+// blocks kept queued on a driver that can't block, enough to ride out a late
+// wakeup
+const TARGET_BLOCKS: u32 = 2;
+
+// Pull-mode stream: the driver lives on its own thread, which asks the
+// callback for interleaved f32 frames whenever the device wants more.
+pub struct AudioCallback {
+    channels: u32,
+    frequency: u32,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl AudioCallback {
+    // `setup` runs on the audio thread right after the driver is opened, the
+    // callback then receives `channels` interleaved samples per frame, in the
+    // layout `setup` left set
+    pub fn new<S, F>(ty: AudioDriverType, setup: S, callback: F) -> Result<AudioCallback, Error>
+    where
+        S: FnOnce(&mut Audio) -> Result<(), Error> + Send + 'static,
+        F: FnMut(&mut [f32]) + Send + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let (opened, receiver) = mpsc::channel();
+
+        let thread = {
+            let running = running.clone();
+            thread::spawn(move || {
+                #[cfg(target_os = "windows")]
+                if let Err(err) = unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) } {
+                    let _ = opened.send(Err(super::wasapi::Error::from(err).into()));
+                    return Ok(());
+                }
+
+                let result = run(ty, setup, callback, &running, opened);
+
+                #[cfg(target_os = "windows")]
+                unsafe {
+                    CoUninitialize()
+                };
+
+                result
+            })
+        };
+
+        match receiver.recv() {
+            Ok(Ok((channels, frequency))) => Ok(AudioCallback {
+                channels,
+                frequency,
+                running,
+                thread: Some(thread),
+            }),
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                Err(err)
+            }
+            Err(_) => match thread.join() {
+                Ok(result) => result.and(Err(Error::new(
+                    ErrorKind::Backend,
+                    "Audio thread exited before opening the device",
+                ))),
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+        }
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    pub fn stop(mut self) -> Result<(), Error> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<(), Error> {
+        self.running.store(false, Ordering::Release);
+
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for AudioCallback {
+    // `stop` is there for callers who want the thread's error
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+type Opened = mpsc::Sender<Result<(u32, u32), Error>>;
+
+fn run<S, F>(
+    ty: AudioDriverType,
+    setup: S,
+    mut callback: F,
+    running: &AtomicBool,
+    opened: Opened,
+) -> Result<(), Error>
+where
+    S: FnOnce(&mut Audio) -> Result<(), Error>,
+    F: FnMut(&mut [f32]),
+{
+    let audio = Audio::new(ty).and_then(|mut audio| {
+        setup(&mut audio)?;
+        if audio.support_blocking() {
+            audio.set_blocking(true)?;
+        }
+        Ok(audio)
+    });
+
+    let mut audio = match audio {
+        Ok(audio) => audio,
+        Err(err) => {
+            let _ = opened.send(Err(err));
+            return Ok(());
+        }
+    };
+
+    let channels = audio.layout().channels();
+    let frequency = audio.frequency();
+    let _ = opened.send(Ok((channels, frequency)));
+
+    let frames = (frequency / BLOCKS_PER_SECOND).max(1);
+    let mut buffer = vec![0.0; (frames * channels) as usize];
+
+    while running.load(Ordering::Acquire) {
+        // without a blocking driver nothing paces the loop, it sleeps while
+        // the queue is above the target and then tops it up, so oversleeping
+        // eats into the queue once instead of adding up
+        if !audio.support_blocking() {
+            let rate = audio.device_frequency();
+            let target = (rate / BLOCKS_PER_SECOND * TARGET_BLOCKS) as usize;
+            let queued = audio.queued_frames();
+            if queued > target {
+                let surplus = (queued - target) as f64 / rate as f64;
+                thread::sleep(Duration::from_secs_f64(surplus));
+                continue;
+            }
+        }
+
+        buffer.fill(0.0);
+        callback(&mut buffer);
+        audio.output_frames(&buffer)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::ChannelLayout;
+    use std::sync::Mutex;
+
+    #[test]
+    fn callback_fills_frames_in_the_layout() {
+        let lengths = Arc::new(Mutex::new(Vec::new()));
+        let callback = {
+            let lengths = lengths.clone();
+            AudioCallback::new(
+                AudioDriverType::None,
+                |audio| audio.set_layout(ChannelLayout::Surround51),
+                move |buffer| lengths.lock().unwrap().push(buffer.len()),
+            )
+            .unwrap()
+        };
+        assert_eq!(callback.channels(), 6);
+
+        thread::sleep(Duration::from_millis(50));
+        let frames = (callback.frequency() / BLOCKS_PER_SECOND) as usize;
+        callback.stop().unwrap();
+
+        let lengths = lengths.lock().unwrap();
+        assert!(!lengths.is_empty());
+        assert!(lengths.iter().all(|&length| length == frames * 6));
+    }
+}
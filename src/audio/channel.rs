@@ -0,0 +1,295 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    SideLeft,
+    SideRight,
+    // a position without a mapping, it never receives any signal
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    #[default]
+    Stereo,
+    Surround21,
+    Quad,
+    Surround51,
+    Surround71,
+}
+
+impl ChannelLayout {
+    pub fn from_channels(channels: u32) -> Option<ChannelLayout> {
+        match channels {
+            1 => Some(ChannelLayout::Mono),
+            2 => Some(ChannelLayout::Stereo),
+            3 => Some(ChannelLayout::Surround21),
+            4 => Some(ChannelLayout::Quad),
+            6 => Some(ChannelLayout::Surround51),
+            8 => Some(ChannelLayout::Surround71),
+            _ => None,
+        }
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.speakers().len() as u32
+    }
+
+    // speakers in interleaving order, the same order WAVE files use
+    pub fn speakers(&self) -> &'static [Speaker] {
+        use Speaker::*;
+
+        match self {
+            ChannelLayout::Mono => &[FrontCenter],
+            ChannelLayout::Stereo => &[FrontLeft, FrontRight],
+            ChannelLayout::Surround21 => &[FrontLeft, FrontRight, LowFrequency],
+            ChannelLayout::Quad => &[FrontLeft, FrontRight, BackLeft, BackRight],
+            ChannelLayout::Surround51 => &[
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackLeft,
+                BackRight,
+            ],
+            ChannelLayout::Surround71 => &[
+                FrontLeft,
+                FrontRight,
+                FrontCenter,
+                LowFrequency,
+                BackLeft,
+                BackRight,
+                SideLeft,
+                SideRight,
+            ],
+        }
+    }
+}
+
+impl std::fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChannelLayout::Mono => write!(f, "Mono"),
+            ChannelLayout::Stereo => write!(f, "Stereo"),
+            ChannelLayout::Surround21 => write!(f, "2.1"),
+            ChannelLayout::Quad => write!(f, "Quad"),
+            ChannelLayout::Surround51 => write!(f, "5.1"),
+            ChannelLayout::Surround71 => write!(f, "7.1"),
+        }
+    }
+}
+
+// speakers for an arbitrary channel count when the device doesn't say
+pub(crate) fn default_speakers(channels: u32) -> Vec<Speaker> {
+    match ChannelLayout::from_channels(channels) {
+        Some(layout) => layout.speakers().to_vec(),
+        None => {
+            let speakers = ChannelLayout::Surround71.speakers().iter().copied();
+            speakers
+                .chain(std::iter::repeat(Speaker::Other))
+                .take(channels as usize)
+                .collect()
+        }
+    }
+}
+
+// Where a source speaker ends up on the output: center and surrounds fold in
+// at -3 dB like the ITU-R BS.775 downmix and LFE is dropped. These are gains
+// before `ChannelMixer` normalizes them.
+fn route(speaker: Speaker, output: &[Speaker], gain: f32, routes: &mut Vec<(usize, f32)>) {
+    use Speaker::*;
+
+    if let Some(index) = output.iter().position(|&it| it == speaker) {
+        routes.push((index, gain));
+        return;
+    }
+
+    let has = |speaker| output.contains(&speaker);
+    match speaker {
+        FrontCenter if has(FrontLeft) && has(FrontRight) => {
+            route(FrontLeft, output, gain * FRAC_1_SQRT_2, routes);
+            route(FrontRight, output, gain * FRAC_1_SQRT_2, routes);
+        }
+        FrontLeft | FrontRight if has(FrontCenter) => {
+            route(FrontCenter, output, gain * 0.5, routes);
+        }
+        BackLeft if has(SideLeft) => route(SideLeft, output, gain, routes),
+        BackRight if has(SideRight) => route(SideRight, output, gain, routes),
+        SideLeft if has(BackLeft) => route(BackLeft, output, gain, routes),
+        SideRight if has(BackRight) => route(BackRight, output, gain, routes),
+        BackLeft | SideLeft => route(FrontLeft, output, gain * FRAC_1_SQRT_2, routes),
+        BackRight | SideRight => route(FrontRight, output, gain * FRAC_1_SQRT_2, routes),
+        _ => {}
+    }
+}
+
+pub(crate) struct ChannelMixer {
+    identity: bool,
+    input: usize,
+    // output-major, `matrix[output * input + input_channel]`
+    matrix: Vec<f32>,
+}
+
+impl ChannelMixer {
+    pub(crate) fn new(input: &[Speaker], output: &[Speaker]) -> ChannelMixer {
+        let mut matrix = vec![0.0; input.len() * output.len()];
+        let mut routes = Vec::new();
+
+        for (channel, &speaker) in input.iter().enumerate() {
+            routes.clear();
+            if input == [Speaker::FrontCenter] && !output.contains(&Speaker::FrontCenter) {
+                // mono plays at full level on both front speakers
+                route(Speaker::FrontLeft, output, 1.0, &mut routes);
+                route(Speaker::FrontRight, output, 1.0, &mut routes);
+            } else {
+                route(speaker, output, 1.0, &mut routes);
+            }
+
+            for &(index, gain) in &routes {
+                matrix[index * input.len() + channel] += gain;
+            }
+        }
+
+        // keep folded down channels from clipping, every row is scaled by the
+        // largest row sum, so 5.1 to stereo plays L at 1 / (1 + 2 * 0.707)
+        // with C and Ls at 0.707 of that rather than BS.775's L + 0.707 C +
+        // 0.707 Ls, which can reach 2.4
+        let peak = matrix
+            .chunks(input.len().max(1))
+            .map(|row| row.iter().sum::<f32>())
+            .fold(1.0, f32::max);
+        matrix.iter_mut().for_each(|gain| *gain /= peak);
+
+        ChannelMixer {
+            identity: input == output,
+            input: input.len(),
+            matrix,
+        }
+    }
+
+    pub(crate) fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    pub(crate) fn output_channels(&self) -> usize {
+        self.matrix.len() / self.input.max(1)
+    }
+
+    pub(crate) fn process(&self, input: &[f32], output: &mut Vec<f32>) {
+        for frame in input.chunks_exact(self.input) {
+            for row in self.matrix.chunks_exact(self.input) {
+                let sample = row.iter().zip(frame.iter()).map(|(&g, &s)| g * s).sum();
+                output.push(sample);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Speaker::*;
+
+    const ALSA_51: [Speaker; 6] = [
+        FrontLeft,
+        FrontRight,
+        BackLeft,
+        BackRight,
+        FrontCenter,
+        LowFrequency,
+    ];
+
+    // `gains[output][input]`, played through the mixer one channel at a time
+    fn gains(input: &[Speaker], output: &[Speaker]) -> Vec<Vec<f32>> {
+        let mixer = ChannelMixer::new(input, output);
+        let mut gains = vec![vec![0.0; input.len()]; output.len()];
+        for channel in 0..input.len() {
+            let mut frame = vec![0.0; input.len()];
+            frame[channel] = 1.0;
+            let mut samples = Vec::new();
+            mixer.process(&frame, &mut samples);
+            for (row, sample) in gains.iter_mut().zip(samples) {
+                row[channel] = sample;
+            }
+        }
+        gains
+    }
+
+    fn assert_gains(gains: Vec<Vec<f32>>, expected: &[&[f32]]) {
+        assert_eq!(gains.len(), expected.len());
+        for (row, expected) in gains.iter().zip(expected) {
+            for (&gain, &expected) in row.iter().zip(expected.iter()) {
+                assert!((gain - expected).abs() < 1e-6, "{:?}", gains);
+            }
+        }
+    }
+
+    #[test]
+    fn mono_plays_on_both_fronts() {
+        let gains = gains(
+            ChannelLayout::Mono.speakers(),
+            ChannelLayout::Stereo.speakers(),
+        );
+        assert_gains(gains, &[&[1.0], &[1.0]]);
+    }
+
+    #[test]
+    fn stereo_folds_to_mono_at_half() {
+        let gains = gains(
+            ChannelLayout::Stereo.speakers(),
+            ChannelLayout::Mono.speakers(),
+        );
+        assert_gains(gains, &[&[0.5, 0.5]]);
+    }
+
+    #[test]
+    fn surround_folds_to_stereo_normalized() {
+        let gains = gains(
+            ChannelLayout::Surround51.speakers(),
+            ChannelLayout::Stereo.speakers(),
+        );
+        let front = 1.0 / (1.0 + 2.0 * FRAC_1_SQRT_2);
+        let folded = FRAC_1_SQRT_2 * front;
+        assert_gains(
+            gains,
+            &[
+                &[front, 0.0, folded, 0.0, folded, 0.0],
+                &[0.0, front, folded, 0.0, 0.0, folded],
+            ],
+        );
+    }
+
+    #[test]
+    fn alsa_order_routes_by_speaker() {
+        let front = 1.0 / (1.0 + 2.0 * FRAC_1_SQRT_2);
+        let folded = FRAC_1_SQRT_2 * front;
+        // the rear pair comes before center and lfe
+        assert_gains(
+            gains(&ALSA_51, ChannelLayout::Stereo.speakers()),
+            &[
+                &[front, 0.0, folded, 0.0, folded, 0.0],
+                &[0.0, front, 0.0, folded, folded, 0.0],
+            ],
+        );
+
+        // the same speakers in another order only move
+        assert_gains(
+            gains(ChannelLayout::Surround51.speakers(), &ALSA_51),
+            &[
+                &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+                &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+                &[0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+                &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+                &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+            ],
+        );
+    }
+}
@@ -0,0 +1,26 @@
+use std::time::{Duration, Instant};
+
+// How much of the stream the listener has heard: `position` device frames
+// had been played at `timestamp`.
+#[derive(Clone, Copy, Debug)]
+pub struct AudioClock {
+    pub position: u64,
+    pub frequency: u32,
+    pub timestamp: Instant,
+}
+
+impl AudioClock {
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.position as f64 / self.frequency as f64)
+    }
+
+    // extrapolates the position to `instant`, assuming playback kept running
+    pub fn position_at(&self, instant: Instant) -> f64 {
+        let delta = if instant >= self.timestamp {
+            (instant - self.timestamp).as_secs_f64()
+        } else {
+            -(self.timestamp - instant).as_secs_f64()
+        };
+        self.position as f64 + delta * self.frequency as f64
+    }
+}
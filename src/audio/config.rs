@@ -0,0 +1,65 @@
+use super::SampleFormat;
+
+// A complete stream setup, checked as a whole and applied with a single
+// reopen by `Audio::configure`. Fields left at `None` keep their current value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AudioConfig {
+    pub device: Option<String>,
+    pub frequency: Option<u32>,
+    pub channels: Option<u32>,
+    pub format: Option<SampleFormat>,
+    pub latency: Option<u32>,
+    pub blocking: Option<bool>,
+    pub exclusive: Option<bool>,
+}
+
+impl AudioConfig {
+    pub fn new() -> AudioConfig {
+        AudioConfig::default()
+    }
+
+    pub fn device(mut self, id: &str) -> AudioConfig {
+        self.device = Some(id.to_string());
+        self
+    }
+
+    pub fn frequency(mut self, frequency: u32) -> AudioConfig {
+        self.frequency = Some(frequency);
+        self
+    }
+
+    pub fn channels(mut self, channels: u32) -> AudioConfig {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn format(mut self, format: SampleFormat) -> AudioConfig {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn latency(mut self, latency: u32) -> AudioConfig {
+        self.latency = Some(latency);
+        self
+    }
+
+    pub fn blocking(mut self, blocking: bool) -> AudioConfig {
+        self.blocking = Some(blocking);
+        self
+    }
+
+    pub fn exclusive(mut self, exclusive: bool) -> AudioConfig {
+        self.exclusive = Some(exclusive);
+        self
+    }
+}
+
+// What the device actually runs at after negotiation, sizes are in frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub frequency: u32,
+    pub channels: u32,
+    pub format: SampleFormat,
+    pub period_size: usize,
+    pub buffer_size: usize,
+}
@@ -0,0 +1,57 @@
+use super::{Biquad, BiquadKind, DspChain};
+
+// The analog stages between a console's sound chip and its output jack. They
+// are part of how the console sounds, a core producing the raw chip output
+// gets them back by pushing `filter()` into `Audio::dsp_chain`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsolePreset {
+    // two coupling caps at 90 and 440 Hz, then the 14 kHz roll-off of the
+    // output amplifier
+    Nes,
+    // the coupling cap the hardware charges by 0.999958 every 4 MiHz clock
+    GameBoy,
+    // a smaller cap, charged by 0.998943 every 4 MiHz clock
+    GameBoyColor,
+    // the VA0-VA6 boards roll off from about 3.4 kHz, which is why model 1
+    // units sound darker than later ones
+    GenesisModel1,
+}
+
+impl ConsolePreset {
+    // a fresh chain per call, each stream keeps its own filter state
+    pub fn filter(&self) -> DspChain {
+        let stage = |kind, frequency| Biquad::new(kind, frequency, 0.0, 0.0);
+        let mut chain = DspChain::new();
+
+        match self {
+            ConsolePreset::Nes => {
+                chain.push(stage(BiquadKind::FirstOrderHighPass, 90.0));
+                chain.push(stage(BiquadKind::FirstOrderHighPass, 440.0));
+                chain.push(stage(BiquadKind::FirstOrderLowPass, 14000.0));
+            }
+            ConsolePreset::GameBoy => {
+                chain.push(stage(
+                    BiquadKind::FirstOrderHighPass,
+                    charge_cutoff(0.999958),
+                ));
+            }
+            ConsolePreset::GameBoyColor => {
+                chain.push(stage(
+                    BiquadKind::FirstOrderHighPass,
+                    charge_cutoff(0.998943),
+                ));
+            }
+            ConsolePreset::GenesisModel1 => {
+                chain.push(stage(BiquadKind::FirstOrderLowPass, 3390.0));
+            }
+        }
+
+        chain
+    }
+}
+
+// the cutoff of a cap that keeps `factor` of its charge per Game Boy clock
+fn charge_cutoff(factor: f64) -> f32 {
+    const CLOCK: f64 = 4194304.0;
+    (-factor.ln() * CLOCK / (2.0 * std::f64::consts::PI)) as f32
+}
@@ -0,0 +1,28 @@
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use super::SampleFormat;
+
+// What a device accepts, empty lists mean it couldn't be probed, e.g. because
+// another application holds it. The lists hold the common values within the
+// hardware ranges, which are `None` where the backend can't tell.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    pub channels: Vec<u32>,
+    pub frequencies: Vec<u32>,
+    pub formats: Vec<SampleFormat>,
+    pub channel_range: Option<RangeInclusive<u32>>,
+    pub frequency_range: Option<RangeInclusive<u32>>,
+    pub buffer_time_range: Option<RangeInclusive<Duration>>,
+    pub period_time_range: Option<RangeInclusive<Duration>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    // stable across runs, pass it to `set_device` and persist it in settings
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub is_default: bool,
+    pub capabilities: DeviceCapabilities,
+}
@@ -0,0 +1,316 @@
+// An effect on interleaved f32 frames. `Audio` runs its chain at the device
+// rate and layout, right before the samples are converted for the backend.
+pub trait Dsp: Send {
+    // called before the first block and whenever the rate or channel count
+    // changes, e.g. after the device was reopened
+    fn prepare(&mut self, frequency: u32, channels: usize);
+
+    fn process(&mut self, samples: &mut [f32]);
+
+    // forgets all history, as if the stream started over
+    fn reset(&mut self);
+
+    // frames the output lags behind the input
+    fn latency(&self) -> usize {
+        0
+    }
+}
+
+// Effects run in order, each on the output of the one before.
+#[derive(Default)]
+pub struct DspChain {
+    channels: usize,
+    effects: Vec<Box<dyn Dsp>>,
+    frequency: u32,
+}
+
+impl DspChain {
+    pub fn new() -> DspChain {
+        DspChain::default()
+    }
+
+    pub fn push(&mut self, effect: impl Dsp + 'static) {
+        self.insert(self.effects.len(), effect);
+    }
+
+    pub fn insert(&mut self, index: usize, mut effect: impl Dsp + 'static) {
+        if self.frequency != 0 {
+            effect.prepare(self.frequency, self.channels);
+        }
+        self.effects.insert(index, Box::new(effect));
+    }
+
+    pub fn remove(&mut self, index: usize) -> Box<dyn Dsp> {
+        self.effects.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut (dyn Dsp + 'static)> {
+        self.effects.get_mut(index).map(|effect| &mut **effect)
+    }
+}
+
+impl Dsp for DspChain {
+    // cheap when nothing changed, so it can be called for every block
+    fn prepare(&mut self, frequency: u32, channels: usize) {
+        if self.frequency == frequency && self.channels == channels {
+            return;
+        }
+        self.frequency = frequency;
+        self.channels = channels;
+        for effect in &mut self.effects {
+            effect.prepare(frequency, channels);
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for effect in &mut self.effects {
+            effect.process(samples);
+        }
+    }
+
+    fn reset(&mut self) {
+        for effect in &mut self.effects {
+            effect.reset();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.effects.iter().map(|effect| effect.latency()).sum()
+    }
+}
+
+// Removes a constant offset with a first order high-pass at a few Hz, cores
+// whose DAC idles away from zero otherwise eat into the headroom.
+pub struct DcBlocker {
+    coefficient: f32,
+    cutoff: f32,
+    // last input and output per channel
+    state: Vec<[f32; 2]>,
+}
+
+impl DcBlocker {
+    pub fn new() -> DcBlocker {
+        DcBlocker::with_cutoff(10.0)
+    }
+
+    pub fn with_cutoff(cutoff: f32) -> DcBlocker {
+        DcBlocker {
+            coefficient: 1.0,
+            cutoff,
+            state: Vec::new(),
+        }
+    }
+}
+
+impl Default for DcBlocker {
+    fn default() -> Self {
+        DcBlocker::new()
+    }
+}
+
+impl Dsp for DcBlocker {
+    fn prepare(&mut self, frequency: u32, channels: usize) {
+        let omega = 2.0 * std::f32::consts::PI * self.cutoff / frequency as f32;
+        self.coefficient = (1.0 - omega).clamp(0.0, 1.0);
+        self.state.resize(channels, [0.0; 2]);
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.state.len().max(1);
+        for frame in samples.chunks_exact_mut(channels) {
+            for (sample, state) in frame.iter_mut().zip(&mut self.state) {
+                let [input, output] = *state;
+                let value = *sample - input + self.coefficient * output;
+                *state = [*sample, value];
+                *sample = value;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state.fill([0.0; 2]);
+    }
+}
+
+// Keeps peaks under `threshold_db`. It looks `lookahead_ms` ahead so the gain
+// is already down when a peak arrives, then recovers over `release_ms`.
+pub struct Limiter {
+    channels: usize,
+    // the last lookahead frames, interleaved
+    delay: Vec<f32>,
+    gain: f32,
+    // required gain of every frame in the lookahead window
+    gains: Vec<f32>,
+    lookahead_ms: f32,
+    position: usize,
+    release: f32,
+    release_ms: f32,
+    threshold: f32,
+}
+
+impl Limiter {
+    pub fn new(threshold_db: f32) -> Limiter {
+        Limiter {
+            channels: 0,
+            delay: Vec::new(),
+            gain: 1.0,
+            gains: Vec::new(),
+            lookahead_ms: 2.0,
+            position: 0,
+            release: 0.0,
+            release_ms: 50.0,
+            threshold: 10f32.powf(threshold_db / 20.0),
+        }
+    }
+
+    pub fn lookahead(mut self, lookahead_ms: f32) -> Limiter {
+        self.lookahead_ms = lookahead_ms.max(0.0);
+        self
+    }
+
+    pub fn release(mut self, release_ms: f32) -> Limiter {
+        self.release_ms = release_ms.max(0.0);
+        self
+    }
+
+    pub fn set_threshold_db(&mut self, threshold_db: f32) {
+        self.threshold = 10f32.powf(threshold_db / 20.0);
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter::new(-1.0)
+    }
+}
+
+impl Dsp for Limiter {
+    fn prepare(&mut self, frequency: u32, channels: usize) {
+        let frames = (self.lookahead_ms * frequency as f32 / 1000.0).round() as usize;
+        let release_frames = self.release_ms * frequency as f32 / 1000.0;
+        self.release = if release_frames > 0.0 {
+            (-1.0 / release_frames).exp()
+        } else {
+            0.0
+        };
+        self.channels = channels;
+        self.delay = vec![0.0; frames * channels];
+        self.gains = vec![1.0; frames + 1];
+        self.position = 0;
+        self.gain = 1.0;
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        if self.gains.is_empty() {
+            return;
+        }
+        let channels = self.channels.max(1);
+        let lookahead = self.gains.len() - 1;
+
+        for frame in samples.chunks_exact_mut(channels) {
+            let peak = frame
+                .iter()
+                .fold(0.0f32, |peak, &sample| peak.max(sample.abs()));
+            let required = if peak > self.threshold {
+                self.threshold / peak
+            } else {
+                1.0
+            };
+            let index = self.position % self.gains.len();
+            self.gains[index] = required;
+
+            // the lowest gain any frame in the window needs, the one leaving
+            // the delay line is in there too
+            let target = self.gains.iter().fold(1.0f32, |gain, &it| gain.min(it));
+            self.gain = if target < self.gain {
+                target
+            } else {
+                target + (self.gain - target) * self.release
+            };
+
+            if lookahead > 0 {
+                let index = (self.position % lookahead) * channels;
+                let delayed = &mut self.delay[index..index + channels];
+                for (sample, delayed) in frame.iter_mut().zip(delayed) {
+                    std::mem::swap(sample, delayed);
+                }
+            }
+            for sample in frame {
+                *sample = (*sample * self.gain).clamp(-self.threshold, self.threshold);
+            }
+            self.position = self.position.wrapping_add(1);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay.fill(0.0);
+        self.gains.fill(1.0);
+        self.gain = 1.0;
+        self.position = 0;
+    }
+
+    fn latency(&self) -> usize {
+        self.gains.len().saturating_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|frame| amplitude * (frame as f32 * 0.05).sin())
+            .collect()
+    }
+
+    #[test]
+    fn dc_blocker_removes_offset() {
+        let mut blocker = DcBlocker::new();
+        blocker.prepare(48000, 2);
+
+        // one second per block, a different offset per channel
+        let mut samples = [0.5, -0.25].repeat(48000);
+        blocker.process(&mut samples);
+        let mut samples = [0.5, -0.25].repeat(48000);
+        blocker.process(&mut samples);
+        assert!(samples.iter().all(|sample| sample.abs() < 1e-3));
+    }
+
+    #[test]
+    fn limiter_keeps_peaks_under_threshold() {
+        let mut limiter = Limiter::new(-6.0);
+        limiter.prepare(48000, 1);
+        let threshold = 10f32.powf(-6.0 / 20.0);
+
+        let mut samples = tone(2.0, 4800);
+        limiter.process(&mut samples);
+        assert!(samples.iter().all(|sample| sample.abs() <= threshold));
+        // turned down, not cut off
+        let peak = samples.iter().fold(0.0f32, |peak, it| peak.max(it.abs()));
+        assert!(peak > threshold * 0.9);
+
+        // input under the threshold passes as it is, only delayed
+        let mut limiter = Limiter::new(-6.0);
+        limiter.prepare(48000, 1);
+        let input = tone(0.25, 4800);
+        let mut samples = input.clone();
+        limiter.process(&mut samples);
+        assert_eq!(
+            samples[limiter.latency()..],
+            input[..4800 - limiter.latency()]
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    // the device buffer ran dry or overflowed
+    Xrun,
+    // the device went away, e.g. it was unplugged or its driver restarted
+    DeviceLost,
+    // another application holds the device
+    DeviceBusy,
+    FormatUnsupported,
+    InvalidConfig,
+    // anything the backend reported that fits none of the above
+    Backend,
+}
+
+impl ErrorKind {
+    // whether reopening the stream is expected to make the error go away
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, ErrorKind::Xrun | ErrorKind::DeviceLost)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Xrun => write!(f, "Buffer underrun or overrun"),
+            ErrorKind::DeviceLost => write!(f, "Device lost"),
+            ErrorKind::DeviceBusy => write!(f, "Device busy"),
+            ErrorKind::FormatUnsupported => write!(f, "Format unsupported"),
+            ErrorKind::InvalidConfig => write!(f, "Invalid config"),
+            ErrorKind::Backend => write!(f, "Backend error"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+    source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Error {
+        Error {
+            kind,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    // wraps a backend error, keeping it reachable through `source()`
+    pub fn with_source(
+        kind: ErrorKind,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Error {
+        Error {
+            kind,
+            message: String::new(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn is_recoverable(&self) -> bool {
+        self.kind.is_recoverable()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
@@ -0,0 +1,19 @@
+// Things that happen to a stream without the caller asking for them,
+// reported through `Audio::set_event_callback`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AudioEvent {
+    // the stream failed in a way reopening may fix, recovery starts now
+    DeviceLost(String),
+    // playback goes on on `device`, `fallback` when that is the default
+    // device standing in for the lost one
+    Recovered { device: String, fallback: bool },
+    // every attempt failed, the error is passed on to the caller
+    RecoveryFailed,
+    // the device ran dry this many times during the last write, it is
+    // playing again but the listener heard a gap
+    Underrun(u64),
+    // this many frames were thrown away instead of played
+    FramesDropped(u64),
+}
+
+pub(crate) type EventCallback = Box<dyn FnMut(&AudioEvent)>;
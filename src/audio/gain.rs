@@ -0,0 +1,62 @@
+// how long a change takes, short enough to feel instant
+const RAMP_MS: u32 = 5;
+
+// Linear gain that glides to a new value instead of jumping to it, a jump
+// in the middle of a waveform is heard as a click.
+pub(crate) struct Gain {
+    current: f32,
+    target: f32,
+    step: f32,
+}
+
+impl Gain {
+    pub fn new(value: f32) -> Gain {
+        Gain {
+            current: value,
+            target: value,
+            step: 0.0,
+        }
+    }
+
+    // reaches `target` after `RAMP_MS` at `frequency`
+    pub fn set(&mut self, target: f32, frequency: u32) {
+        let frames = (frequency * RAMP_MS / 1000).max(1);
+        self.target = target;
+        self.step = (target - self.current) / frames as f32;
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.current == 0.0 && self.target == 0.0
+    }
+
+    pub fn is_unity(&self) -> bool {
+        self.current == 1.0 && self.target == 1.0
+    }
+
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if self.current == self.target {
+            let gain = self.current;
+            samples.iter_mut().for_each(|sample| *sample *= gain);
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(channels) {
+            if self.current != self.target {
+                self.current += self.step;
+                let past = if self.step > 0.0 {
+                    self.current >= self.target
+                } else {
+                    self.current <= self.target
+                };
+                if past {
+                    self.current = self.target;
+                }
+            }
+            frame.iter_mut().for_each(|sample| *sample *= self.current);
+        }
+    }
+}
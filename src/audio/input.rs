@@ -0,0 +1,199 @@
+use std::time::Instant;
+
+use super::{
+    AudioConfig, AudioDriverType, AudioStats, DeviceInfo, Error, ErrorKind, Sample, SampleFormat,
+    StreamFormat,
+};
+
+pub trait AudioInputDriver {
+    fn driver(&self) -> &'static str {
+        "None"
+    }
+
+    fn support_device_list(&self) -> Vec<DeviceInfo> {
+        Vec::new()
+    }
+
+    // the only way to change the stream, fields that don't match what the
+    // driver runs are rejected unless it overrides this
+    fn configure(&mut self, config: &AudioConfig) -> Result<AudioConfig, Error> {
+        let current = self.config();
+        let asked = AudioConfig {
+            device: config.device.clone().or(current.device.clone()),
+            frequency: config.frequency.or(current.frequency),
+            channels: config.channels.or(current.channels),
+            format: config.format.or(current.format),
+            latency: config.latency.or(current.latency),
+            blocking: config.blocking.or(current.blocking),
+            exclusive: config.exclusive.or(current.exclusive),
+        };
+        if asked != current {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "The input stream can't be changed",
+            ));
+        }
+        Ok(current)
+    }
+
+    fn config(&self) -> AudioConfig {
+        let format = self.current_format();
+        AudioConfig {
+            frequency: Some(format.frequency),
+            channels: Some(format.channels),
+            format: Some(format.format),
+            ..AudioConfig::default()
+        }
+    }
+
+    fn current_format(&self) -> StreamFormat;
+
+    // frames that can be read without waiting
+    fn available_frames(&self) -> usize {
+        0
+    }
+
+    // fills whole frames of `samples` and returns how many, a blocking stream
+    // waits until all of them are there
+    fn read_frames(&mut self, samples: &mut [f32]) -> Result<usize, Error>;
+
+    // what happened since the last call, `AudioInput` adds it up
+    fn take_stats(&mut self) -> AudioStats {
+        AudioStats::default()
+    }
+}
+
+// Hears silence, delivered in real time so a caller pacing itself on the
+// input keeps its speed.
+#[derive(Default)]
+pub struct NullInput {
+    read: u64,
+    started: Option<Instant>,
+}
+
+impl AudioInputDriver for NullInput {
+    fn current_format(&self) -> StreamFormat {
+        StreamFormat {
+            frequency: 48000,
+            channels: 1,
+            format: SampleFormat::F32,
+            period_size: 0,
+            buffer_size: 0,
+        }
+    }
+
+    fn available_frames(&self) -> usize {
+        let frequency = self.current_format().frequency as f64;
+        let elapsed = self
+            .started
+            .map_or(0.0, |started| started.elapsed().as_secs_f64());
+        ((elapsed * frequency) as u64).saturating_sub(self.read) as usize
+    }
+
+    fn read_frames(&mut self, samples: &mut [f32]) -> Result<usize, Error> {
+        // like a device, the stream starts with the first read
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+
+        let channels = self.current_format().channels as usize;
+        let frames = self.available_frames().min(samples.len() / channels);
+        samples[..frames * channels].fill(0.0);
+        self.read += frames as u64;
+        Ok(frames)
+    }
+}
+
+// Capture counterpart of `Audio`: no mixing or resampling, the samples come
+// in the layout and rate `current_format` reports.
+pub struct AudioInput {
+    instance: Box<dyn AudioInputDriver>,
+    buffer: Vec<f32>,
+    stats: AudioStats,
+}
+
+impl AudioInput {
+    pub fn new(ty: AudioDriverType) -> Result<Self, Error> {
+        let instance: Box<dyn AudioInputDriver> = match ty {
+            #[cfg(target_os = "windows")]
+            AudioDriverType::WASAPI => {
+                return Err(Error::new(
+                    ErrorKind::Backend,
+                    "WASAPI has no input support yet",
+                ))
+            }
+            #[cfg(target_os = "linux")]
+            AudioDriverType::ALSA => Box::new(super::alsa::ALSAInputDriver::new()?),
+            _ => Box::new(NullInput::default()),
+        };
+
+        Ok(AudioInput {
+            instance,
+            buffer: Vec::new(),
+            stats: AudioStats::default(),
+        })
+    }
+
+    pub fn driver(&self) -> &'static str {
+        self.instance.driver()
+    }
+
+    pub fn support_device_list(&self) -> Vec<DeviceInfo> {
+        self.instance.support_device_list()
+    }
+
+    pub fn set_device(&mut self, id: &str) -> Result<(), Error> {
+        self.configure(&AudioConfig::new().device(id))?;
+        Ok(())
+    }
+
+    // values that weren't asked for are kept, the ones asked for may be
+    // negotiated to the nearest the device takes, the result tells
+    pub fn configure(&mut self, config: &AudioConfig) -> Result<AudioConfig, Error> {
+        self.instance.configure(config)
+    }
+
+    pub fn config(&self) -> AudioConfig {
+        self.instance.config()
+    }
+
+    pub fn current_format(&self) -> StreamFormat {
+        self.instance.current_format()
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.instance.current_format().channels
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.instance.current_format().frequency
+    }
+
+    pub fn available_frames(&self) -> usize {
+        self.instance.available_frames()
+    }
+
+    // reads interleaved frames into `samples`, returns how many frames it got
+    pub fn read<S: Sample>(&mut self, samples: &mut [S]) -> Result<usize, Error> {
+        let channels = self.channels() as usize;
+        let length = samples.len() / channels * channels;
+        self.buffer.resize(length, 0.0);
+
+        let result = self.instance.read_frames(&mut self.buffer);
+        self.stats.merge(&self.instance.take_stats());
+        let frames = result?;
+
+        for (sample, &value) in samples.iter_mut().zip(&self.buffer[..frames * channels]) {
+            *sample = S::from_f32(value);
+        }
+        Ok(frames)
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = AudioStats::default();
+    }
+}
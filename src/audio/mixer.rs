@@ -0,0 +1,316 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::channel::ChannelMixer;
+use super::gain::Gain;
+use super::resampler::Resampler;
+use super::{
+    ring_buffer, Audio, ChannelLayout, Consumer, Error, ErrorKind, Producer, ResamplerQuality,
+    Sample,
+};
+
+// how much a source can queue ahead of the mixer
+const SOURCE_BUFFER_MS: u32 = 200;
+
+// the sum stays linear up to here and bends towards full scale above it,
+// so several loud sources saturate softly instead of clipping
+const KNEE: f32 = 0.8;
+
+// what a source handle shares with the mixer
+struct Control {
+    // f32 bits
+    gain: AtomicU32,
+    removed: AtomicBool,
+}
+
+// The producing end of one mixer input. Dropping it removes the source once
+// it has faded out.
+pub struct MixerSource {
+    control: Arc<Control>,
+    frequency: u32,
+    layout: ChannelLayout,
+    producer: Producer<f32>,
+    scratch: Vec<f32>,
+}
+
+impl MixerSource {
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    // queues interleaved frames in the source's layout, returns how many
+    // samples were taken, the rest didn't fit
+    pub fn push<S: Sample>(&mut self, samples: &[S]) -> usize {
+        let channels = self.layout.channels() as usize;
+        let free = self.producer.free() / channels * channels;
+        let count = (samples.len() / channels * channels).min(free);
+
+        self.scratch.clear();
+        self.scratch
+            .extend(samples[..count].iter().map(|&sample| sample.to_f32()));
+        self.producer.push_slice(&self.scratch)
+    }
+
+    pub fn queued_frames(&self) -> usize {
+        self.producer.len() / self.layout.channels() as usize
+    }
+
+    // linear, changes are ramped like `Audio::set_volume`
+    pub fn set_gain(&self, gain: f32) {
+        let gain = if gain.is_finite() { gain.max(0.0) } else { 0.0 };
+        self.control.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.control.gain.load(Ordering::Relaxed))
+    }
+}
+
+impl Drop for MixerSource {
+    fn drop(&mut self) {
+        self.control.removed.store(true, Ordering::Release);
+    }
+}
+
+// the mixer's end of a source, already converted to the output layout and rate
+struct Entry {
+    channels: usize,
+    consumer: Consumer<f32>,
+    control: Arc<Control>,
+    gain: Gain,
+    input: Vec<f32>,
+    mixed: Vec<f32>,
+    mixer: ChannelMixer,
+    // converted frames not mixed yet
+    pending: Vec<f32>,
+    ratio: f64,
+    resampler: Resampler,
+}
+
+impl Entry {
+    // converts queued input until `samples` output samples are pending or
+    // the source runs dry
+    fn fill(&mut self, samples: usize) {
+        let output = self.mixer.output_channels();
+        while self.pending.len() < samples && !self.consumer.is_empty() {
+            let frames = (samples - self.pending.len()) / output;
+            let frames = (frames as f64 / self.ratio).ceil() as usize + 1;
+            self.input.resize(frames * self.channels, 0.0);
+            let count = self.consumer.pop_slice(&mut self.input);
+            self.input.truncate(count);
+
+            let input = if self.mixer.is_identity() {
+                &self.input
+            } else {
+                self.mixed.clear();
+                self.mixer.process(&self.input, &mut self.mixed);
+                &self.mixed
+            };
+            if self.ratio == 1.0 {
+                self.pending.extend_from_slice(input);
+            } else {
+                self.resampler.process(input, self.ratio, &mut self.pending);
+            }
+        }
+    }
+}
+
+// the same check `Audio::set_frequency` makes, a rate of 0 has no ratio
+fn check_frequency(frequency: u32) -> Result<(), Error> {
+    if frequency == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidConfig,
+            format!("Frequency {} is not supported", frequency),
+        ));
+    }
+    Ok(())
+}
+
+struct Shared {
+    added: Mutex<Vec<Entry>>,
+    frequency: u32,
+    layout: ChannelLayout,
+}
+
+impl Shared {
+    fn add_source(&self, frequency: u32, layout: ChannelLayout) -> Result<MixerSource, Error> {
+        check_frequency(frequency)?;
+        let channels = layout.channels() as usize;
+        let capacity = (frequency * SOURCE_BUFFER_MS / 1000) as usize * channels;
+        let (producer, consumer) = ring_buffer(capacity);
+        let control = Arc::new(Control {
+            gain: AtomicU32::new(1f32.to_bits()),
+            removed: AtomicBool::new(false),
+        });
+
+        let ratio = self.frequency as f64 / frequency as f64;
+        let output = self.layout.channels() as usize;
+        // fade in so a source starting mid-waveform doesn't click
+        let mut gain = Gain::new(0.0);
+        gain.set(1.0, self.frequency);
+
+        self.added.lock().unwrap().push(Entry {
+            channels,
+            consumer,
+            control: control.clone(),
+            gain,
+            input: Vec::new(),
+            mixed: Vec::new(),
+            mixer: ChannelMixer::new(layout.speakers(), self.layout.speakers()),
+            pending: Vec::new(),
+            ratio,
+            resampler: Resampler::new(output, ResamplerQuality::default(), ratio),
+        });
+
+        Ok(MixerSource {
+            control,
+            frequency,
+            layout,
+            producer,
+            scratch: Vec::new(),
+        })
+    }
+}
+
+// Adds sources to a `Mixer` from other threads, e.g. while the mixer runs
+// inside an `AudioCallback`.
+#[derive(Clone)]
+pub struct MixerHandle {
+    shared: Arc<Shared>,
+}
+
+impl MixerHandle {
+    pub fn add_source(&self, frequency: u32, layout: ChannelLayout) -> Result<MixerSource, Error> {
+        self.shared.add_source(frequency, layout)
+    }
+}
+
+// Sums any number of sources, each with its own rate, layout and gain, into
+// one stream at `frequency` in `layout`.
+pub struct Mixer {
+    buffer: Vec<f32>,
+    entries: Vec<Entry>,
+    shared: Arc<Shared>,
+}
+
+impl Mixer {
+    pub fn new(frequency: u32, layout: ChannelLayout) -> Result<Mixer, Error> {
+        check_frequency(frequency)?;
+        Ok(Mixer {
+            buffer: Vec::new(),
+            entries: Vec::new(),
+            shared: Arc::new(Shared {
+                added: Mutex::new(Vec::new()),
+                frequency,
+                layout,
+            }),
+        })
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.shared.frequency
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.shared.layout
+    }
+
+    pub fn handle(&self) -> MixerHandle {
+        MixerHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub fn add_source(&self, frequency: u32, layout: ChannelLayout) -> Result<MixerSource, Error> {
+        self.shared.add_source(frequency, layout)
+    }
+
+    // sources still playing, including ones fading out
+    pub fn sources(&self) -> usize {
+        self.entries.len()
+    }
+
+    // fills `output` with interleaved frames, sources that run dry are
+    // silent until they catch up
+    pub fn process(&mut self, output: &mut [f32]) {
+        // never wait on a thread adding a source, it's picked up next time
+        if let Ok(mut added) = self.shared.added.try_lock() {
+            self.entries.append(&mut added);
+        }
+
+        let channels = self.shared.layout.channels() as usize;
+        let frequency = self.shared.frequency;
+        let length = output.len() / channels * channels;
+        output.fill(0.0);
+
+        for entry in &mut self.entries {
+            let gain = if entry.control.removed.load(Ordering::Acquire) {
+                0.0
+            } else {
+                f32::from_bits(entry.control.gain.load(Ordering::Relaxed))
+            };
+            if gain != entry.gain.target() {
+                entry.gain.set(gain, frequency);
+            }
+
+            entry.fill(length);
+            let count = entry.pending.len().min(length);
+            entry.gain.process(&mut entry.pending[..count], channels);
+            for (output, &sample) in output.iter_mut().zip(&entry.pending[..count]) {
+                *output += sample;
+            }
+            entry.pending.drain(..count);
+        }
+
+        self.entries.retain(|entry| {
+            let removed = entry.control.removed.load(Ordering::Acquire);
+            let playing = !entry.pending.is_empty() || !entry.consumer.is_empty();
+            !removed || (playing && !entry.gain.is_silent())
+        });
+
+        for sample in output {
+            let level = sample.abs();
+            if level > KNEE {
+                let over = (level - KNEE) / (1.0 - KNEE);
+                *sample = sample.signum() * (KNEE + (1.0 - KNEE) * over.tanh());
+            }
+        }
+    }
+
+    // mixes `frames` frames and plays them, `audio` is switched to the
+    // mixer's rate and layout first if needed
+    pub fn output(&mut self, audio: &mut Audio, frames: usize) -> Result<(), Error> {
+        if audio.layout() != self.shared.layout {
+            audio.set_layout(self.shared.layout)?;
+        }
+        if audio.frequency() != self.shared.frequency {
+            audio.set_frequency(self.shared.frequency)?;
+        }
+
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.resize(frames * self.shared.layout.channels() as usize, 0.0);
+        self.process(&mut buffer);
+        let result = audio.output_frames(&buffer);
+        self.buffer = buffer;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_a_rate_of_zero() {
+        assert!(Mixer::new(0, ChannelLayout::Stereo).is_err());
+
+        let mixer = Mixer::new(48000, ChannelLayout::Stereo).unwrap();
+        assert!(mixer.add_source(0, ChannelLayout::Mono).is_err());
+        assert!(mixer.handle().add_source(0, ChannelLayout::Mono).is_err());
+        assert!(mixer.add_source(32040, ChannelLayout::Mono).is_ok());
+    }
+}
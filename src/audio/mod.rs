@@ -4,6 +4,56 @@ mod wasapi;
 #[cfg(target_os = "linux")]
 mod alsa;
 
+use std::time::{Duration, Instant};
+
+mod biquad;
+mod blip;
+mod callback;
+mod channel;
+mod clock;
+mod config;
+mod console;
+mod device;
+mod dsp;
+mod error;
+mod event;
+mod gain;
+mod input;
+mod mixer;
+mod recovery;
+mod resampler;
+mod ring;
+mod sample;
+mod stats;
+mod stretch;
+mod watcher;
+
+use channel::ChannelMixer;
+use event::EventCallback;
+use gain::Gain;
+use resampler::Resampler;
+
+pub use biquad::{Biquad, BiquadKind, ParametricEq};
+pub use blip::BlipBuffer;
+pub use callback::AudioCallback;
+pub use channel::{ChannelLayout, Speaker};
+pub use clock::AudioClock;
+pub use config::{AudioConfig, StreamFormat};
+pub use console::ConsolePreset;
+pub use device::{DeviceCapabilities, DeviceInfo};
+pub use dsp::{DcBlocker, Dsp, DspChain, Limiter};
+pub use error::{Error, ErrorKind};
+pub use event::AudioEvent;
+pub use input::{AudioInput, AudioInputDriver, NullInput};
+pub use mixer::{Mixer, MixerHandle, MixerSource};
+pub use recovery::RecoveryPolicy;
+pub use resampler::ResamplerQuality;
+pub use ring::{ring_buffer, Consumer, Producer};
+pub use sample::{Sample, SampleFormat, I24};
+pub use stats::AudioStats;
+pub use stretch::TimeStretch;
+pub use watcher::{DeviceEvent, DeviceSource, DeviceWatcher};
+
 pub enum AudioDriverType {
     #[cfg(target_os = "windows")]
     WASAPI,
@@ -12,58 +62,6 @@ pub enum AudioDriverType {
     None,
 }
 
-pub enum Error {
-    NoDevice,
-    DeviceNotFound(String),
-    Unsupported(String),
-    #[cfg(target_os = "windows")]
-    WASAPIError(wasapi::Error),
-    #[cfg(target_os = "linux")]
-    ALSAError(alsa::Error),
-}
-
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Error::NoDevice => write!(f, "NotDevice"),
-            Error::DeviceNotFound(device) => write!(f, "Device {} not found", device),
-            Error::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
-            #[cfg(target_os = "windows")]
-            Error::WASAPIError(err) => write!(f, "WASAPIError: {}", err),
-            #[cfg(target_os = "linux")]
-            Error::ALSAError(err) => write!(f, "ALSAError: {}", err),
-        }
-    }
-}
-
-impl std::fmt::Debug for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Error::NoDevice => write!(f, "NotDevice"),
-            Error::DeviceNotFound(device) => write!(f, "Device {} not found", device),
-            Error::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
-            #[cfg(target_os = "windows")]
-            Error::WASAPIError(err) => write!(f, "WASAPIError: {}", err),
-            #[cfg(target_os = "linux")]
-            Error::ALSAError(err) => write!(f, "ALSAError: {}", err),
-        }
-    }
-}
-
-#[cfg(target_os = "windows")]
-impl From<wasapi::Error> for Error {
-    fn from(err: wasapi::Error) -> Self {
-        Error::WASAPIError(err)
-    }
-}
-
-#[cfg(target_os = "linux")]
-impl From<alsa::Error> for Error {
-    fn from(err: alsa::Error) -> Self {
-        Error::ALSAError(err)
-    }
-}
-
 pub trait AudioDriver {
     fn driver(&self) -> &'static str {
         "None"
@@ -73,7 +71,7 @@ pub trait AudioDriver {
         false
     }
 
-    fn support_device_list(&self) -> Vec<String> {
+    fn support_device_list(&self) -> Vec<DeviceInfo> {
         Vec::new()
     }
 
@@ -93,16 +91,38 @@ pub trait AudioDriver {
         Vec::new()
     }
 
+    // whether the device takes a value, including ones between the listed
+    // steps when it has a continuous range
+    fn test_channels(&self, channels: u32) -> bool {
+        self.support_channels().contains(&channels)
+    }
+
+    fn test_frequency(&self, frequency: u32) -> bool {
+        self.support_frequencies().contains(&frequency)
+    }
+
+    fn test_latency(&self, latency: u32) -> bool {
+        self.support_latencies().contains(&latency)
+    }
+
     fn set_exclusive(&mut self, exclusive: bool) -> Result<(), Error> {
         let _ = exclusive;
         Ok(())
     }
 
-    fn set_device(&mut self, device: &str) -> Result<(), Error> {
-        let _ = device;
+    // lists the devices again, e.g. after a `DeviceWatcher` reported a change
+    fn refresh_devices(&mut self) -> Result<(), Error> {
         Ok(())
     }
 
+    // `id` is one of the `DeviceInfo::id`s from `support_device_list`
+    fn set_device(&mut self, id: &str) -> Result<(), Error> {
+        Err(Error::new(
+            ErrorKind::InvalidConfig,
+            format!("Device {} not found", id),
+        ))
+    }
+
     fn set_blocking(&mut self, blocking: bool) -> Result<(), Error> {
         let _ = blocking;
         Ok(())
@@ -123,56 +143,299 @@ pub trait AudioDriver {
         Ok(())
     }
 
-    fn output(&mut self, samples: &[f64]) -> Result<(), Error> {
-        let _ = samples;
+    // checks every field first so a bad config changes nothing, then applies
+    // them, drivers that have to reopen override this to do it only once
+    fn configure(&mut self, config: &AudioConfig) -> Result<AudioConfig, Error> {
+        let invalid = |what: &str| Err(Error::new(ErrorKind::InvalidConfig, what));
+
+        if let Some(id) = &config.device {
+            if !self.support_device_list().iter().any(|it| it.id == *id) {
+                return invalid(&format!("Device {} not found", id));
+            }
+        }
+        if config.frequency.is_some_and(|it| !self.test_frequency(it)) {
+            return invalid("frequency");
+        }
+        if config.channels.is_some_and(|it| !self.test_channels(it)) {
+            return invalid("channels");
+        }
+        if config.latency.is_some_and(|it| !self.test_latency(it)) {
+            return invalid("latency");
+        }
+        if config.blocking == Some(true) && !self.support_blocking() {
+            return invalid("blocking");
+        }
+        if config.exclusive == Some(true) && !self.support_exclusive() {
+            return invalid("exclusive");
+        }
+        if let Some(format) = config.format.filter(|&it| it != self.sample_format()) {
+            return Err(Error::new(ErrorKind::FormatUnsupported, format.to_string()));
+        }
+
+        if let Some(id) = &config.device {
+            self.set_device(id)?;
+        }
+        if let Some(exclusive) = config.exclusive {
+            self.set_exclusive(exclusive)?;
+        }
+        if let Some(frequency) = config.frequency {
+            self.set_frequency(frequency)?;
+        }
+        if let Some(channels) = config.channels {
+            self.set_channels(channels)?;
+        }
+        if let Some(latency) = config.latency {
+            self.set_latency(latency)?;
+        }
+        if let Some(blocking) = config.blocking {
+            self.set_blocking(blocking)?;
+        }
+
+        Ok(self.config())
+    }
+
+    // the stream as it runs, with everything the driver knows filled in
+    fn config(&self) -> AudioConfig {
+        AudioConfig {
+            frequency: Some(self.frequency()),
+            channels: Some(self.channels()),
+            format: Some(self.sample_format()),
+            ..AudioConfig::default()
+        }
+    }
+
+    fn current_format(&self) -> StreamFormat {
+        StreamFormat {
+            frequency: self.frequency(),
+            channels: self.channels(),
+            format: self.sample_format(),
+            period_size: 0,
+            buffer_size: self.buffer_capacity_frames(),
+        }
+    }
+
+    fn channels(&self) -> u32 {
+        2
+    }
+
+    fn speakers(&self) -> Vec<Speaker> {
+        channel::default_speakers(self.channels())
+    }
+
+    fn frequency(&self) -> u32 {
+        48000
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        SampleFormat::F32
+    }
+
+    fn queued_frames(&self) -> usize {
+        0
+    }
+
+    fn buffer_capacity_frames(&self) -> usize {
+        0
+    }
+
+    fn estimated_latency(&self) -> Duration {
+        Duration::from_secs_f64(self.queued_frames() as f64 / self.frequency() as f64)
+    }
+
+    fn clock(&self) -> Option<AudioClock> {
+        None
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        false
+    }
+
+    // blocks until everything queued has been played, resuming if paused
+    fn drain(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // discards everything queued without playing it
+    fn flush(&mut self) -> Result<(), Error> {
         Ok(())
     }
 
-    fn output_i16(&mut self, samples: &[i16]) -> Result<(), Error> {
+    // opens the current config again on `id`, or on the default device for
+    // `None`, carrying over the samples still queued in software
+    fn reopen(&mut self, id: Option<&str>) -> Result<(), Error> {
+        let _ = id;
+        Err(Error::new(ErrorKind::Backend, "Reopen is not supported"))
+    }
+
+    // what happened since the last call, `Audio` adds it up
+    fn take_stats(&mut self) -> AudioStats {
+        AudioStats::default()
+    }
+
+    fn output_frames(&mut self, samples: &[f32]) -> Result<(), Error> {
         let _ = samples;
         Ok(())
     }
 }
 
-pub struct NullDriver;
+// Discards everything, but pretends to play it in real time so the clock and
+// queue depth behave like a device's.
+#[derive(Default)]
+pub struct NullDriver {
+    paused: bool,
+    played: u64,
+    updated: Option<Instant>,
+    written: u64,
+}
 
-impl AudioDriver for NullDriver {}
+impl NullDriver {
+    fn played(&self, now: Instant) -> u64 {
+        let elapsed = self
+            .updated
+            .map_or(0.0, |updated| (now - updated).as_secs_f64());
+        let played = self.played + (elapsed * self.frequency() as f64) as u64;
+        played.min(self.written)
+    }
+}
+
+impl AudioDriver for NullDriver {
+    fn queued_frames(&self) -> usize {
+        (self.written - self.played(Instant::now())) as usize
+    }
+
+    fn clock(&self) -> Option<AudioClock> {
+        let now = Instant::now();
+        Some(AudioClock {
+            position: self.played(now),
+            frequency: self.frequency(),
+            timestamp: now,
+        })
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.played = self.played(Instant::now());
+        self.updated = None;
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        if self.paused {
+            self.updated = Some(Instant::now());
+            self.paused = false;
+        }
+        Ok(())
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn drain(&mut self) -> Result<(), Error> {
+        self.resume()?;
+        std::thread::sleep(self.estimated_latency());
+        self.played = self.written;
+        self.updated = Some(Instant::now());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.played = self.played(Instant::now());
+        self.written = self.played;
+        Ok(())
+    }
+
+    fn output_frames(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let now = Instant::now();
+        self.played = self.played(now);
+        if !self.paused {
+            self.updated = Some(now);
+        }
+        self.written += (samples.len() / self.channels() as usize) as u64;
+        Ok(())
+    }
+}
 
 pub struct Audio {
     instance: Box<dyn AudioDriver>,
+    buffer: Vec<f32>,
+    dsp: DspChain,
+    frequency: u32,
+    gain: Gain,
+    layout: ChannelLayout,
+    mixer: ChannelMixer,
+    muted: bool,
+    quality: ResamplerQuality,
+    on_event: Option<EventCallback>,
+    rate_control: f64,
+    recovery: Option<RecoveryPolicy>,
+    resampled: Vec<f32>,
+    resampler: Resampler,
+    speed: f64,
+    stats: AudioStats,
+    stretch: Option<TimeStretch>,
+    volume: f32,
 }
 
 impl Audio {
     pub fn new(ty: AudioDriverType) -> Result<Self, Error> {
-        match ty {
+        let instance: Box<dyn AudioDriver> = match ty {
             #[cfg(target_os = "windows")]
-            AudioDriverType::WASAPI => Ok(Audio {
-                instance: Box::new(wasapi::WASAPIDriver::new()?),
-            }),
+            AudioDriverType::WASAPI => Box::new(wasapi::WASAPIDriver::new()?),
             #[cfg(target_os = "linux")]
-            AudioDriverType::ALSA => Ok(Audio {
-                instance: Box::new(alsa::ALSADriver::new()?),
-            }),
-            _ => Ok(Audio {
-                instance: Box::new(NullDriver),
-            }),
-        }
+            AudioDriverType::ALSA => Box::new(alsa::ALSADriver::new()?),
+            _ => Box::new(NullDriver::default()),
+        };
+        let frequency = instance.frequency();
+        let layout = ChannelLayout::default();
+        let mixer = ChannelMixer::new(layout.speakers(), &instance.speakers());
+        let quality = ResamplerQuality::default();
+        let resampler = Resampler::new(instance.channels() as usize, quality, 1.0);
+
+        Ok(Audio {
+            instance,
+            buffer: Vec::new(),
+            dsp: DspChain::new(),
+            frequency,
+            gain: Gain::new(1.0),
+            layout,
+            mixer,
+            muted: false,
+            quality,
+            on_event: None,
+            rate_control: 0.0,
+            recovery: None,
+            resampled: Vec::new(),
+            resampler,
+            speed: 1.0,
+            stats: AudioStats::default(),
+            stretch: None,
+            volume: 1.0,
+        })
     }
 
     pub fn support_drivers() -> Vec<&'static str> {
-        let mut drivers = Vec::new();
-        #[cfg(target_os = "windows")]
-        drivers.push("WASAPI");
-        #[cfg(target_os = "linux")]
-        drivers.push("ALSA");
-        drivers
+        vec![
+            #[cfg(target_os = "windows")]
+            "WASAPI",
+            #[cfg(target_os = "linux")]
+            "ALSA",
+        ]
     }
 
     pub fn support_exclusive(&self) -> bool {
         self.instance.support_exclusive()
     }
 
-    pub fn support_device_list(&self) -> Vec<String> {
+    pub fn support_device_list(&self) -> Vec<DeviceInfo> {
         self.instance.support_device_list()
     }
 
@@ -196,77 +459,488 @@ impl Audio {
         if self.instance.support_exclusive() {
             self.instance.set_exclusive(exclusive)
         } else {
-            Err(Error::Unsupported(
-                "Exclusive mode is not supported".to_string(),
+            Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "Exclusive mode is not supported",
             ))
         }
     }
 
-    pub fn set_device(&mut self, device: &str) -> Result<(), Error> {
-        if self
-            .instance
-            .support_device_list()
-            .contains(&device.to_string())
-        {
-            self.instance.set_device(device)
-        } else {
-            Err(Error::Unsupported(format!(
-                "Device {} is not supported",
-                device
-            )))
-        }
+    pub fn refresh_devices(&mut self) -> Result<(), Error> {
+        self.instance.refresh_devices()
+    }
+
+    // the driver checks the id itself, listing devices may have to probe them
+    pub fn set_device(&mut self, id: &str) -> Result<(), Error> {
+        self.instance.set_device(id)?;
+        self.update_mixer();
+        Ok(())
     }
 
     pub fn set_blocking(&mut self, blocking: bool) -> Result<(), Error> {
         if self.instance.support_blocking() {
             self.instance.set_blocking(blocking)
         } else {
-            Err(Error::Unsupported(
-                "Blocking mode is not supported".to_string(),
+            Err(Error::new(
+                ErrorKind::InvalidConfig,
+                "Blocking mode is not supported",
             ))
         }
     }
 
     pub fn set_channels(&mut self, channels: u32) -> Result<(), Error> {
-        if self.instance.support_channels().contains(&channels) {
-            self.instance.set_channels(channels)
+        if self.instance.test_channels(channels) {
+            self.instance.set_channels(channels)?;
+            self.update_mixer();
+            Ok(())
         } else {
-            Err(Error::Unsupported(format!(
-                "Channels {} is not supported",
-                channels
-            )))
+            Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Channels {} is not supported", channels),
+            ))
         }
     }
 
+    // any input rate is accepted, the device is switched to it when it is
+    // one of its listed rates and the stream is resampled otherwise
     pub fn set_frequency(&mut self, frequency: u32) -> Result<(), Error> {
-        if self.instance.support_frequencies().contains(&frequency) {
-            self.instance.set_frequency(frequency)
-        } else {
-            Err(Error::Unsupported(format!(
-                "Frequency {} is not supported",
-                frequency
-            )))
+        if frequency == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Frequency {} is not supported", frequency),
+            ));
+        }
+
+        if self.is_native_frequency(frequency) && frequency != self.instance.frequency() {
+            self.instance.set_frequency(frequency)?;
+        }
+
+        self.frequency = frequency;
+        self.resampler.reset();
+        Ok(())
+    }
+
+    // applies a whole config at once, the input rate follows the requested
+    // frequency and is resampled when the device settles on another one
+    pub fn configure(&mut self, config: &AudioConfig) -> Result<AudioConfig, Error> {
+        if config.frequency == Some(0) {
+            return Err(Error::new(ErrorKind::InvalidConfig, "frequency"));
+        }
+
+        // like `set_frequency`, a rate the device doesn't list is resampled
+        // rather than passed on, where it would be rejected or converted
+        let mut device_config = config.clone();
+        if config
+            .frequency
+            .is_some_and(|it| !self.is_native_frequency(it))
+        {
+            device_config.frequency = None;
+        }
+
+        let negotiated = self.instance.configure(&device_config)?;
+        if let Some(frequency) = config.frequency {
+            self.frequency = frequency;
+        }
+        self.resampler.reset();
+        self.update_mixer();
+        Ok(negotiated)
+    }
+
+    pub fn config(&self) -> AudioConfig {
+        self.instance.config()
+    }
+
+    pub fn current_format(&self) -> StreamFormat {
+        self.instance.current_format()
+    }
+
+    // effects run on every block at the device rate and layout, after the
+    // resampler and before the volume
+    pub fn dsp_chain(&mut self) -> &mut DspChain {
+        &mut self.dsp
+    }
+
+    pub fn set_dsp_chain(&mut self, chain: DspChain) {
+        self.dsp = chain;
+    }
+
+    // plays the pushed samples `speed` times faster at their original pitch,
+    // so a core fast-forwarding at 4x pushes four times the samples and the
+    // device still gets real-time length; below 1.0 it plays in slow motion
+    pub fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+        if !(speed > 0.0 && speed.is_finite()) {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("speed: {}", speed),
+            ));
         }
+        self.speed = speed;
+        Ok(())
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // linear, 1.0 leaves the samples as they are, above it they may clip
+    pub fn set_volume(&mut self, volume: f32) -> Result<(), Error> {
+        if !(volume >= 0.0 && volume.is_finite()) {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("volume: {}", volume),
+            ));
+        }
+        self.volume = volume;
+        self.update_gain();
+        Ok(())
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    // 0 dB is unity, `f32::NEG_INFINITY` is silence
+    pub fn set_volume_db(&mut self, db: f32) -> Result<(), Error> {
+        if db.is_nan() {
+            return Err(Error::new(ErrorKind::InvalidConfig, "volume: NaN dB"));
+        }
+        self.set_volume(10f32.powf(db / 20.0))
+    }
+
+    pub fn volume_db(&self) -> f32 {
+        20.0 * self.volume.log10()
+    }
+
+    // keeps the volume, so unmuting goes back to it
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.update_gain();
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    fn update_gain(&mut self) {
+        let target = if self.muted { 0.0 } else { self.volume };
+        self.gain.set(target, self.instance.frequency());
+    }
+
+    pub fn set_resampler_quality(&mut self, quality: ResamplerQuality) -> Result<(), Error> {
+        self.quality = quality;
+        self.resampler = Resampler::new(self.resampler.channels(), quality, self.resampler.ratio());
+        Ok(())
     }
 
     pub fn set_latency(&mut self, latency: u32) -> Result<(), Error> {
-        if self.instance.support_latencies().contains(&latency) {
+        if self.instance.test_latency(latency) {
             self.instance.set_latency(latency)
         } else {
-            Err(Error::Unsupported(format!(
-                "Latency {} is not supported",
-                latency
-            )))
+            Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Latency {} is not supported", latency),
+            ))
         }
     }
 
-    pub fn output(&mut self, sample: &[f64]) -> Result<(), Error> {
-        self.instance.output(sample)?;
+    // the layout of the frames passed to `output`, mixed to the device's
+    pub fn set_layout(&mut self, layout: ChannelLayout) -> Result<(), Error> {
+        self.layout = layout;
+        self.update_mixer();
         Ok(())
     }
 
-    pub fn output_i16(&mut self, sample: &[i16]) -> Result<(), Error> {
-        self.instance.output_i16(&sample)?;
+    // plugin devices like ALSA's `default` take any rate and convert it
+    // themselves, only the listed ones are worth reopening the device for
+    fn is_native_frequency(&self, frequency: u32) -> bool {
+        self.instance.support_frequencies().contains(&frequency)
+    }
+
+    fn update_mixer(&mut self) {
+        self.mixer = ChannelMixer::new(self.layout.speakers(), &self.instance.speakers());
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    pub fn channels(&self) -> u32 {
+        self.instance.channels()
+    }
+
+    pub fn speakers(&self) -> Vec<Speaker> {
+        self.instance.speakers()
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        self.instance.sample_format()
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    pub fn device_frequency(&self) -> u32 {
+        self.instance.frequency()
+    }
+
+    pub fn resampler_quality(&self) -> ResamplerQuality {
+        self.quality
+    }
+
+    pub fn queued_frames(&self) -> usize {
+        self.instance.queued_frames()
+    }
+
+    pub fn buffer_capacity_frames(&self) -> usize {
+        self.instance.buffer_capacity_frames()
+    }
+
+    // includes the delay of the effects and what the time stretch holds back
+    pub fn estimated_latency(&self) -> Duration {
+        let effects = self.dsp.latency() as f64 / self.instance.frequency() as f64;
+        let stretch = self.stretch.as_ref().map_or(0.0, |stretch| {
+            stretch.latency() as f64 / self.frequency as f64
+        });
+        self.instance.estimated_latency() + Duration::from_secs_f64(effects + stretch)
+    }
+
+    pub fn clock(&self) -> Option<AudioClock> {
+        self.instance.clock()
+    }
+
+    pub fn pause(&mut self) -> Result<(), Error> {
+        self.instance.pause()
+    }
+
+    pub fn resume(&mut self) -> Result<(), Error> {
+        self.instance.resume()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.instance.is_paused()
+    }
+
+    pub fn drain(&mut self) -> Result<(), Error> {
+        if let Some(stretch) = &mut self.stretch {
+            self.buffer.clear();
+            stretch.flush(&mut self.buffer);
+            let channels = stretch.channels();
+            self.render(channels)?;
+        }
+
+        let result = self.instance.drain();
+        self.update_stats();
+        result
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.resampler.reset();
+        self.dsp.reset();
+        self.stretch = None;
+        self.instance.flush()
+    }
+
+    pub fn rate_control(&self) -> f64 {
+        self.rate_control
+    }
+
+    // keeps the device buffer half full by stretching the stream by at most
+    // `max_deviation` (0.005 = ±0.5%), 0 turns it off
+    pub fn set_rate_control(&mut self, max_deviation: f64) -> Result<(), Error> {
+        if !(0.0..0.1).contains(&max_deviation) {
+            return Err(Error::new(
+                ErrorKind::InvalidConfig,
+                format!("Rate control deviation {} is not supported", max_deviation),
+            ));
+        }
+
+        self.rate_control = max_deviation;
+        self.resampler.reset();
         Ok(())
     }
+
+    fn rate_adjust(&self) -> f64 {
+        let capacity = self.instance.buffer_capacity_frames();
+        if self.rate_control == 0.0 || capacity == 0 {
+            return 1.0;
+        }
+
+        let fill = (self.instance.queued_frames() as f64 / capacity as f64).min(1.0);
+        // an emptying buffer produces more frames, a filling one fewer
+        1.0 + self.rate_control * (1.0 - 2.0 * fill)
+    }
+
+    pub fn output<S: Sample>(&mut self, frame: &[S]) -> Result<(), Error> {
+        let channels = self.layout.channels() as usize;
+        self.output_frames(&frame[..channels])
+    }
+
+    // `samples` holds interleaved frames in the layout set by `set_layout`
+    pub fn output_frames<S: Sample>(&mut self, samples: &[S]) -> Result<(), Error> {
+        let channels = self.layout.channels() as usize;
+        self.buffer.clear();
+        self.buffer.extend(
+            samples[..samples.len() / channels * channels]
+                .iter()
+                .map(|&sample| sample.to_f32()),
+        );
+
+        let channels = self.instance.channels() as usize;
+        if self.mixer.output_channels() != channels {
+            self.update_mixer();
+        }
+
+        if !self.mixer.is_identity() {
+            self.resampled.clear();
+            self.mixer.process(&self.buffer, &mut self.resampled);
+            std::mem::swap(&mut self.buffer, &mut self.resampled);
+        }
+
+        if self.speed != 1.0 || self.stretch.is_some() {
+            let frequency = self.frequency;
+            let stretch = self
+                .stretch
+                .get_or_insert_with(|| TimeStretch::new(channels, frequency));
+            if stretch.channels() != channels || stretch.frequency() != frequency {
+                *stretch = TimeStretch::new(channels, frequency);
+            }
+            stretch.set_speed(self.speed);
+
+            self.resampled.clear();
+            stretch.process(&self.buffer, &mut self.resampled);
+            // back at 1.0 what it held goes out with this block and the
+            // samples pass untouched from the next one on
+            if self.speed == 1.0 {
+                stretch.flush(&mut self.resampled);
+                self.stretch = None;
+            }
+            std::mem::swap(&mut self.buffer, &mut self.resampled);
+        }
+
+        self.render(channels)
+    }
+
+    // the rest of the way for the device's channels at the input rate
+    fn render(&mut self, channels: usize) -> Result<(), Error> {
+        let ratio = self.instance.frequency() as f64 / self.frequency as f64;
+        if self.rate_control != 0.0 || ratio != 1.0 {
+            if self.resampler.channels() != channels || self.resampler.ratio() != ratio {
+                self.resampler = Resampler::new(channels, self.quality, ratio);
+            }
+
+            self.resampled.clear();
+            self.resampler.process(
+                &self.buffer,
+                ratio * self.rate_adjust(),
+                &mut self.resampled,
+            );
+            std::mem::swap(&mut self.buffer, &mut self.resampled);
+        }
+
+        if !self.dsp.is_empty() {
+            self.dsp.prepare(self.instance.frequency(), channels);
+            self.dsp.process(&mut self.buffer);
+        }
+
+        if !self.gain.is_unity() {
+            self.gain.process(&mut self.buffer, channels);
+        }
+
+        let result = match self.instance.output_frames(&self.buffer) {
+            Err(err) if err.is_recoverable() && self.recovery.is_some() => self.recover(err),
+            result => result,
+        };
+        self.update_stats();
+        result
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = AudioStats::default();
+    }
+
+    fn update_stats(&mut self) {
+        let stats = self.instance.take_stats();
+        self.stats.merge(&stats);
+
+        if stats.underruns > 0 {
+            self.emit(AudioEvent::Underrun(stats.underruns));
+        }
+        if stats.dropped_frames > 0 {
+            self.emit(AudioEvent::FramesDropped(stats.dropped_frames));
+        }
+    }
+
+    // `None` leaves every failure to the caller, which is the default
+    pub fn set_recovery_policy(&mut self, policy: Option<RecoveryPolicy>) {
+        self.recovery = policy;
+    }
+
+    pub fn recovery_policy(&self) -> Option<RecoveryPolicy> {
+        self.recovery
+    }
+
+    // called on the thread that drives `Audio`, keep it short
+    pub fn set_event_callback<F: FnMut(&AudioEvent) + 'static>(&mut self, callback: F) {
+        self.on_event = Some(Box::new(callback));
+    }
+
+    fn emit(&mut self, event: AudioEvent) {
+        if let Some(on_event) = &mut self.on_event {
+            on_event(&event);
+        }
+    }
+
+    // Everything queued before is played on the new stream, and the block of
+    // the failed call is written again once the device is back. It is only
+    // lost when the new stream runs with another channel count or rate.
+    fn recover(&mut self, err: Error) -> Result<(), Error> {
+        let policy = self.recovery.unwrap_or_default();
+        let device = self.instance.config().device;
+        let channels = self.instance.channels() as usize;
+        let frequency = self.instance.frequency();
+        self.emit(AudioEvent::DeviceLost(err.to_string()));
+
+        let mut backoff = policy.backoff;
+        for _ in 0..policy.attempts {
+            std::thread::sleep(backoff);
+            backoff *= 2;
+
+            if self.instance.reopen(device.as_deref()).is_ok() {
+                self.stats.recoveries += 1;
+                self.emit(AudioEvent::Recovered {
+                    device: device.unwrap_or_default(),
+                    fallback: false,
+                });
+                return self.replay(channels, frequency);
+            }
+        }
+
+        if policy.fallback && self.instance.reopen(None).is_ok() {
+            self.stats.recoveries += 1;
+            self.emit(AudioEvent::Recovered {
+                device: self.instance.config().device.unwrap_or_default(),
+                fallback: true,
+            });
+            return self.replay(channels, frequency);
+        }
+
+        self.stats.dropped_frames += (self.buffer.len() / channels) as u64;
+        self.emit(AudioEvent::RecoveryFailed);
+        Err(err)
+    }
+
+    // writes the block that failed to the reopened stream
+    fn replay(&mut self, channels: usize, frequency: u32) -> Result<(), Error> {
+        self.update_mixer();
+        if self.instance.channels() as usize != channels || self.instance.frequency() != frequency {
+            self.stats.dropped_frames += (self.buffer.len() / channels) as u64;
+            return Ok(());
+        }
+        self.instance.output_frames(&self.buffer)
+    }
 }
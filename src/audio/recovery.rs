@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+// How `Audio` reacts to a lost device: reopen it up to `attempts` times,
+// waiting `backoff` before the first try and twice as long before each next
+// one, then move to the default device if `fallback` is set. The waits run
+// inside the `output_frames` call that hit the error, on the caller's thread,
+// so the default gives up after 350 ms and a few dropped video frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub fallback: bool,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> RecoveryPolicy {
+        RecoveryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(50),
+            fallback: true,
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    Linear,
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ResamplerQuality {
+    fn taps(&self) -> usize {
+        match self {
+            ResamplerQuality::Linear => 2,
+            ResamplerQuality::Low => 8,
+            ResamplerQuality::Medium => 16,
+            ResamplerQuality::High => 32,
+        }
+    }
+
+    // passband edge relative to the lower of the two nyquist frequencies
+    fn cutoff(&self) -> f64 {
+        match self {
+            ResamplerQuality::Linear => 1.0,
+            ResamplerQuality::Low => 0.85,
+            ResamplerQuality::Medium => 0.91,
+            ResamplerQuality::High => 0.95,
+        }
+    }
+}
+
+// kernel phases between two input frames, interpolated linearly
+const PHASES: usize = 256;
+
+pub(crate) fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+pub(crate) fn blackman(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+// Streaming resampler for interleaved frames. The ratio is output rate over
+// input rate and may change between calls, which is what rate control needs.
+pub(crate) struct Resampler {
+    channels: usize,
+    history: Vec<f32>,
+    ratio: f64,
+    table: Vec<f32>,
+    taps: usize,
+    // position of the next output frame, in input frames from history[0]
+    time: f64,
+    weights: Vec<f32>,
+}
+
+impl Resampler {
+    // `ratio` is the nominal output/input ratio the filter is designed for
+    pub(crate) fn new(channels: usize, quality: ResamplerQuality, ratio: f64) -> Resampler {
+        // when downsampling the cutoff drops with the ratio, the kernel has to
+        // grow by as much to still span the same number of sinc lobes
+        let taps = if quality == ResamplerQuality::Linear || ratio >= 1.0 {
+            quality.taps()
+        } else {
+            ((quality.taps() as f64 / ratio).ceil() as usize + 1) & !1
+        };
+        let half = taps / 2;
+        let cutoff = quality.cutoff() * ratio.min(1.0);
+
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let fraction = phase as f64 / PHASES as f64;
+            let row = (0..taps).map(|tap| {
+                let x = tap as f64 - (half - 1) as f64 - fraction;
+                if quality == ResamplerQuality::Linear {
+                    (1.0 - x.abs()).max(0.0)
+                } else {
+                    cutoff * sinc(cutoff * x) * blackman(x / half as f64)
+                }
+            });
+            let row = row.collect::<Vec<_>>();
+            let sum = row.iter().sum::<f64>();
+            table.extend(row.iter().map(|&weight| (weight / sum) as f32));
+        }
+
+        let mut resampler = Resampler {
+            channels,
+            history: Vec::new(),
+            ratio,
+            table,
+            taps,
+            time: 0.0,
+            weights: vec![0.0; taps],
+        };
+        resampler.reset();
+        resampler
+    }
+
+    pub(crate) fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub(crate) fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    pub(crate) fn reset(&mut self) {
+        let half = self.taps / 2;
+        self.history.clear();
+        self.history.resize((half - 1) * self.channels, 0.0);
+        self.time = (half - 1) as f64;
+    }
+
+    pub(crate) fn process(&mut self, input: &[f32], ratio: f64, output: &mut Vec<f32>) {
+        let channels = self.channels;
+        let taps = self.taps;
+        let half = taps / 2;
+        let step = 1.0 / ratio;
+
+        self.history.extend_from_slice(input);
+        let frames = self.history.len() / channels;
+
+        while (self.time as usize) + half < frames {
+            let index = self.time as usize;
+            let position = (self.time - index as f64) * PHASES as f64;
+            let phase = position as usize;
+            let mix = (position - phase as f64) as f32;
+
+            let current = &self.table[phase * taps..(phase + 1) * taps];
+            let next = &self.table[(phase + 1) * taps..(phase + 2) * taps];
+            for (weight, (&current, &next)) in
+                self.weights.iter_mut().zip(current.iter().zip(next.iter()))
+            {
+                *weight = current + (next - current) * mix;
+            }
+
+            let start = (index + 1 - half) * channels;
+            let window = &self.history[start..start + taps * channels];
+            for channel in 0..channels {
+                let sample = window
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .zip(self.weights.iter())
+                    .map(|(&sample, &weight)| sample * weight)
+                    .sum::<f32>();
+                output.push(sample);
+            }
+
+            self.time += step;
+        }
+
+        let consumed = ((self.time as usize) + 1 - half).min(frames);
+        self.history.drain(..consumed * channels);
+        self.time -= consumed as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(frequency: f64, rate: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|frame| (2.0 * PI * frequency * frame as f64 / rate).sin() as f32)
+            .collect()
+    }
+
+    fn resample(input: &[f32], from: f64, to: f64) -> Vec<f32> {
+        let mut resampler = Resampler::new(1, ResamplerQuality::Medium, to / from);
+        let mut output = Vec::new();
+        for block in input.chunks(4096) {
+            resampler.process(block, to / from, &mut output);
+        }
+        output
+    }
+
+    // amplitude of one frequency, skipping the filter's start-up
+    fn amplitude(samples: &[f32], frequency: f64, rate: f64) -> f64 {
+        let samples = &samples[samples.len() / 4..];
+        let (sin, cos) =
+            samples
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(sin, cos), (frame, &sample)| {
+                    let (s, c) = (2.0 * PI * frequency * frame as f64 / rate).sin_cos();
+                    (sin + sample as f64 * s, cos + sample as f64 * c)
+                });
+        2.0 * (sin * sin + cos * cos).sqrt() / samples.len() as f64
+    }
+
+    fn db(amplitude: f64) -> f64 {
+        20.0 * amplitude.log10()
+    }
+
+    #[test]
+    fn upsampling_keeps_passband_and_rejects_images() {
+        let (from, to) = (32040.0, 48000.0);
+
+        for frequency in [1000.0, 10000.0] {
+            let output = resample(&tone(frequency, from, 32040), from, to);
+            assert!(db(amplitude(&output, frequency, to)).abs() < 0.1);
+        }
+
+        // a 4 kHz tone has an image at 28040 Hz, which would fold to 19960 Hz
+        let output = resample(&tone(4000.0, from, 32040), from, to);
+        assert!(db(amplitude(&output, to - (from - 4000.0), to)) < -60.0);
+    }
+
+    #[test]
+    fn downsampling_rejects_what_would_alias() {
+        let (from, to) = (1789773.0, 48000.0);
+
+        let output = resample(&tone(5000.0, from, 178977), from, to);
+        assert!(db(amplitude(&output, 5000.0, to)).abs() < 0.1);
+
+        // both would land on audible frequencies without the low-pass
+        for frequency in [100000.0, 500000.0] {
+            let output = resample(&tone(frequency, from, 178977), from, to);
+            let aliased = (frequency % to).min(to - frequency % to);
+            assert!(db(amplitude(&output, aliased, to)) < -60.0);
+        }
+    }
+}
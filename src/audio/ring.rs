@@ -0,0 +1,316 @@
+#[cfg(loom)]
+use loom::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
+};
+#[cfg(not(loom))]
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+#[cfg(not(loom))]
+struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    fn new(value: T) -> Self {
+        UnsafeCell(std::cell::UnsafeCell::new(value))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+struct Shared<T> {
+    buffer: Box<[UnsafeCell<T>]>,
+    mask: usize,
+    // both positions only ever grow and wrap around usize, the capacity is a
+    // power of two so `position & mask` stays continuous across the wrap
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// slots between head and tail belong to the consumer, the rest to the producer
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+}
+
+// Wait-free single-producer/single-consumer queue with a fixed capacity,
+// allocated once up front. The capacity is rounded up to a power of two.
+pub fn ring_buffer<T: Copy + Default + Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let buffer = (0..capacity)
+        .map(|_| UnsafeCell::new(T::default()))
+        .collect::<Vec<_>>()
+        .into_boxed_slice();
+
+    let shared = Arc::new(Shared {
+        buffer,
+        mask: capacity - 1,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Copy> Producer<T> {
+    pub fn capacity(&self) -> usize {
+        self.shared.buffer.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn free(&self) -> usize {
+        self.capacity() - self.len()
+    }
+
+    // returns how many values fit, the rest of `values` is left untouched
+    pub fn push_slice(&mut self, values: &[T]) -> usize {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        let head = shared.head.load(Ordering::Acquire);
+
+        let count = (shared.buffer.len() - tail.wrapping_sub(head)).min(values.len());
+        for (offset, &value) in values[..count].iter().enumerate() {
+            let slot = &shared.buffer[tail.wrapping_add(offset) & shared.mask];
+            slot.with_mut(|slot| unsafe { *slot = value });
+        }
+
+        shared
+            .tail
+            .store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Copy> Consumer<T> {
+    pub fn capacity(&self) -> usize {
+        self.shared.buffer.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // copies the oldest values into `values` without consuming them
+    pub fn peek_slice(&self, values: &mut [T]) -> usize {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+
+        let count = tail.wrapping_sub(head).min(values.len());
+        for (offset, value) in values[..count].iter_mut().enumerate() {
+            let slot = &shared.buffer[head.wrapping_add(offset) & shared.mask];
+            *value = slot.with(|slot| unsafe { *slot });
+        }
+
+        count
+    }
+
+    pub fn consume(&mut self, count: usize) -> usize {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        let tail = shared.tail.load(Ordering::Acquire);
+
+        let count = tail.wrapping_sub(head).min(count);
+        shared
+            .head
+            .store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    pub fn pop_slice(&mut self, values: &mut [T]) -> usize {
+        let count = self.peek_slice(values);
+        self.consume(count)
+    }
+
+    pub fn clear(&mut self) {
+        self.consume(usize::MAX);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capacity_is_power_of_two() {
+        let (producer, consumer) = ring_buffer::<f32>(600);
+        assert_eq!(producer.capacity(), 1024);
+        assert_eq!(consumer.capacity(), 1024);
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        assert!(consumer.is_empty());
+
+        assert_eq!(producer.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(consumer.len(), 3);
+
+        let mut values = [0; 2];
+        assert_eq!(consumer.pop_slice(&mut values), 2);
+        assert_eq!(values, [1, 2]);
+        assert_eq!(producer.free(), 3);
+    }
+
+    #[test]
+    fn push_stops_when_full() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        assert_eq!(producer.push_slice(&[1, 2, 3, 4, 5, 6]), 4);
+        assert_eq!(producer.push_slice(&[7]), 0);
+
+        let mut values = [0; 8];
+        assert_eq!(consumer.pop_slice(&mut values), 4);
+        assert_eq!(values[..4], [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        let mut values = [0; 3];
+
+        for round in 0..10 {
+            let input = [round * 3, round * 3 + 1, round * 3 + 2];
+            assert_eq!(producer.push_slice(&input), 3);
+            assert_eq!(consumer.pop_slice(&mut values), 3);
+            assert_eq!(values, input);
+        }
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let (mut producer, mut consumer) = ring_buffer(8);
+        producer.push_slice(&[1, 2, 3]);
+
+        let mut values = [0; 3];
+        assert_eq!(consumer.peek_slice(&mut values), 3);
+        assert_eq!(consumer.len(), 3);
+
+        assert_eq!(consumer.consume(2), 2);
+        assert_eq!(consumer.peek_slice(&mut values), 1);
+        assert_eq!(values[0], 3);
+
+        consumer.clear();
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.consume(1), 0);
+    }
+
+    #[test]
+    fn keeps_order_across_threads() {
+        const COUNT: u32 = 20_000;
+        let (mut producer, mut consumer) = ring_buffer(256);
+
+        let thread = std::thread::spawn(move || {
+            let mut next = 0;
+            let mut values = [0; 100];
+            while next < COUNT {
+                for (offset, value) in values.iter_mut().enumerate() {
+                    *value = next + offset as u32;
+                }
+                let count = values.len().min((COUNT - next) as usize);
+                next += producer.push_slice(&values[..count]) as u32;
+            }
+        });
+
+        let mut expected = 0;
+        let mut values = [0; 64];
+        while expected < COUNT {
+            let count = consumer.pop_slice(&mut values);
+            for &value in &values[..count] {
+                assert_eq!(value, expected);
+                expected += 1;
+            }
+        }
+
+        thread.join().unwrap();
+        assert!(consumer.is_empty());
+    }
+}
+
+// run with `RUSTFLAGS="--cfg loom" cargo test --release ring`
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+
+    #[test]
+    fn producer_and_consumer() {
+        loom::model(|| {
+            let (mut producer, mut consumer) = ring_buffer(2);
+
+            let thread = loom::thread::spawn(move || {
+                let mut pushed = 0;
+                while pushed < 3 {
+                    pushed += producer.push_slice(&[1, 2, 3][pushed..]);
+                    loom::thread::yield_now();
+                }
+            });
+
+            let mut received = Vec::new();
+            let mut values = [0; 2];
+            while received.len() < 3 {
+                let count = consumer.pop_slice(&mut values);
+                received.extend_from_slice(&values[..count]);
+                loom::thread::yield_now();
+            }
+
+            thread.join().unwrap();
+            assert_eq!(received, [1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn peek_then_consume() {
+        loom::model(|| {
+            let (mut producer, mut consumer) = ring_buffer(2);
+
+            let thread = loom::thread::spawn(move || {
+                producer.push_slice(&[7, 8]);
+            });
+
+            let mut values = [0; 2];
+            let count = consumer.peek_slice(&mut values);
+            assert!(values[..count].iter().zip([7, 8]).all(|(&a, b)| a == b));
+            assert_eq!(consumer.consume(count), count);
+
+            thread.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,283 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    S16,
+    // 24-bit samples stored in the low bytes of a 32-bit container
+    S24,
+    S32,
+    F32,
+    F64,
+}
+
+impl SampleFormat {
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16 => 2,
+            SampleFormat::S24 | SampleFormat::S32 | SampleFormat::F32 => 4,
+            SampleFormat::F64 => 8,
+        }
+    }
+}
+
+impl std::fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SampleFormat::U8 => write!(f, "U8"),
+            SampleFormat::S16 => write!(f, "S16"),
+            SampleFormat::S24 => write!(f, "S24"),
+            SampleFormat::S32 => write!(f, "S32"),
+            SampleFormat::F32 => write!(f, "F32"),
+            SampleFormat::F64 => write!(f, "F64"),
+        }
+    }
+}
+
+// a signed 24-bit sample in an `i32`, in the range `-8388608..=8388607`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct I24(pub i32);
+
+pub trait Sample: Copy + Send + 'static {
+    const FORMAT: SampleFormat;
+
+    fn to_f32(self) -> f32;
+
+    fn from_f32(value: f32) -> Self;
+
+    fn write_le(self, output: &mut [u8]);
+
+    fn read_le(input: &[u8]) -> Self;
+}
+
+impl Sample for u8 {
+    const FORMAT: SampleFormat = SampleFormat::U8;
+
+    fn to_f32(self) -> f32 {
+        (self as f32 - 128.0) / 128.0
+    }
+
+    fn from_f32(value: f32) -> Self {
+        (value.clamp(-1.0, 1.0) * 127.0 + 128.0) as u8
+    }
+
+    fn write_le(self, output: &mut [u8]) {
+        output[0] = self;
+    }
+
+    fn read_le(input: &[u8]) -> Self {
+        input[0]
+    }
+}
+
+impl Sample for i16 {
+    const FORMAT: SampleFormat = SampleFormat::S16;
+
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+
+    fn from_f32(value: f32) -> Self {
+        (value.clamp(-1.0, 1.0) * 32767.0) as i16 // 2^15 - 1
+    }
+
+    fn write_le(self, output: &mut [u8]) {
+        output.copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(input: &[u8]) -> Self {
+        i16::from_le_bytes([input[0], input[1]])
+    }
+}
+
+impl Sample for I24 {
+    const FORMAT: SampleFormat = SampleFormat::S24;
+
+    fn to_f32(self) -> f32 {
+        self.0 as f32 / 8388608.0
+    }
+
+    fn from_f32(value: f32) -> Self {
+        I24((value.clamp(-1.0, 1.0) * 8388607.0) as i32) // 2^23 - 1
+    }
+
+    fn write_le(self, output: &mut [u8]) {
+        output.copy_from_slice(&self.0.to_le_bytes());
+    }
+
+    fn read_le(input: &[u8]) -> Self {
+        // the container's top byte isn't reliably sign extended
+        let value = i32::from_le_bytes([input[0], input[1], input[2], input[3]]);
+        I24(value << 8 >> 8)
+    }
+}
+
+impl Sample for i32 {
+    const FORMAT: SampleFormat = SampleFormat::S32;
+
+    fn to_f32(self) -> f32 {
+        (self as f64 / 2147483648.0) as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        (value.clamp(-1.0, 1.0) as f64 * 2147483647.0) as i32 // 2^31 - 1
+    }
+
+    fn write_le(self, output: &mut [u8]) {
+        output.copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(input: &[u8]) -> Self {
+        i32::from_le_bytes([input[0], input[1], input[2], input[3]])
+    }
+}
+
+impl Sample for f32 {
+    const FORMAT: SampleFormat = SampleFormat::F32;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.clamp(-1.0, 1.0)
+    }
+
+    fn write_le(self, output: &mut [u8]) {
+        output.copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(input: &[u8]) -> Self {
+        f32::from_le_bytes([input[0], input[1], input[2], input[3]])
+    }
+}
+
+impl Sample for f64 {
+    const FORMAT: SampleFormat = SampleFormat::F64;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.clamp(-1.0, 1.0) as f64
+    }
+
+    fn write_le(self, output: &mut [u8]) {
+        output.copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_le(input: &[u8]) -> Self {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&input[..8]);
+        f64::from_le_bytes(bytes)
+    }
+}
+
+fn write_as<S: Sample>(input: &[f32], output: &mut [u8]) {
+    for (output, &sample) in output.chunks_exact_mut(S::FORMAT.bytes()).zip(input.iter()) {
+        S::from_f32(sample).write_le(output);
+    }
+}
+
+// converts normalized samples into the device format, `output` must hold
+// `input.len() * format.bytes()` bytes
+pub(crate) fn write_samples(format: SampleFormat, input: &[f32], output: &mut [u8]) {
+    match format {
+        SampleFormat::U8 => write_as::<u8>(input, output),
+        SampleFormat::S16 => write_as::<i16>(input, output),
+        SampleFormat::S24 => write_as::<I24>(input, output),
+        SampleFormat::S32 => write_as::<i32>(input, output),
+        SampleFormat::F32 => write_as::<f32>(input, output),
+        SampleFormat::F64 => write_as::<f64>(input, output),
+    }
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn read_as<S: Sample>(input: &[u8], output: &mut [f32]) {
+    for (input, sample) in input.chunks_exact(S::FORMAT.bytes()).zip(output.iter_mut()) {
+        *sample = S::read_le(input).to_f32();
+    }
+}
+
+// the reverse of `write_samples`, `input` holds `output.len() * format.bytes()`
+// bytes in the device format
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn read_samples(format: SampleFormat, input: &[u8], output: &mut [f32]) {
+    match format {
+        SampleFormat::U8 => read_as::<u8>(input, output),
+        SampleFormat::S16 => read_as::<i16>(input, output),
+        SampleFormat::S24 => read_as::<I24>(input, output),
+        SampleFormat::S32 => read_as::<i32>(input, output),
+        SampleFormat::F32 => read_as::<f32>(input, output),
+        SampleFormat::F64 => read_as::<f64>(input, output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [SampleFormat; 6] = [
+        SampleFormat::U8,
+        SampleFormat::S16,
+        SampleFormat::S24,
+        SampleFormat::S32,
+        SampleFormat::F32,
+        SampleFormat::F64,
+    ];
+
+    #[test]
+    fn round_trips_within_a_step() {
+        let input = [-1.0, -0.5, -1e-3, 0.0, 0.25, 0.999, 1.0];
+
+        for format in FORMATS {
+            // the integer formats scale by 2^(n-1) - 1 one way and 2^(n-1)
+            // the other, truncating on the way out
+            let step = match format {
+                SampleFormat::U8 => 2.0 / 128.0,
+                SampleFormat::S16 => 2.0 / 32768.0,
+                SampleFormat::S24 => 2.0 / 8388608.0,
+                SampleFormat::S32 => 2.0 / 2147483648.0,
+                SampleFormat::F32 | SampleFormat::F64 => 0.0,
+            };
+
+            let mut bytes = vec![0; input.len() * format.bytes()];
+            write_samples(format, &input, &mut bytes);
+            let mut output = [0.0; 7];
+            read_samples(format, &bytes, &mut output);
+
+            for (&input, &output) in input.iter().zip(&output) {
+                assert!(
+                    (input - output).abs() <= step,
+                    "{}: {} came back as {}",
+                    format,
+                    input,
+                    output
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clamps_out_of_range() {
+        assert_eq!(i16::from_f32(2.0), 32767);
+        assert_eq!(i16::from_f32(-2.0), -32767);
+        assert_eq!(u8::from_f32(-2.0), 1);
+        assert_eq!(I24::from_f32(2.0), I24(8388607));
+        assert_eq!(f32::from_f32(f32::INFINITY), 1.0);
+    }
+
+    #[test]
+    fn i24_sign_extends_from_bit_23() {
+        // whatever the top byte of the container holds
+        assert_eq!(I24::read_le(&[0x00, 0x00, 0x80, 0x00]), I24(-8388608));
+        assert_eq!(I24::read_le(&[0xff, 0xff, 0xff, 0x12]), I24(-1));
+        assert_eq!(I24::read_le(&[0x01, 0x00, 0x00, 0xff]), I24(1));
+        assert_eq!(I24::read_le(&[0xff, 0xff, 0x7f, 0x80]), I24(8388607));
+
+        let mut bytes = [0; 4];
+        I24(-2).write_le(&mut bytes);
+        assert_eq!(I24::read_le(&bytes), I24(-2));
+        assert_eq!(I24(-8388608).to_f32(), -1.0);
+    }
+}
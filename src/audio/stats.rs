@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+// Counters for one `Audio` or `AudioInput` stream, cumulative until
+// `reset_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AudioStats {
+    // times the device ran dry and had to be restarted
+    pub underruns: u64,
+    // times an input device filled up before it was read
+    pub overruns: u64,
+    // lost devices brought back by the recovery policy
+    pub recoveries: u64,
+    // frames that were queued but never reached the device
+    pub dropped_frames: u64,
+    // longest single hand-over of samples to the device, waiting excluded
+    pub max_write_time: Duration,
+    // total time spent waiting for room in the device buffer, or for input
+    pub blocking_time: Duration,
+}
+
+impl AudioStats {
+    pub(crate) fn merge(&mut self, other: &AudioStats) {
+        self.underruns += other.underruns;
+        self.overruns += other.overruns;
+        self.recoveries += other.recoveries;
+        self.dropped_frames += other.dropped_frames;
+        self.max_write_time = self.max_write_time.max(other.max_write_time);
+        self.blocking_time += other.blocking_time;
+    }
+}
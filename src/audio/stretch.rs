@@ -0,0 +1,295 @@
+// length of the pieces the input is cut into, long enough to hold a few
+// periods of the lowest notes a console plays
+const SEQUENCE_MS: u32 = 40;
+
+// how far past the nominal position a piece may start to line up with the
+// previous one
+const SEARCH_MS: u32 = 15;
+
+// crossfade between two pieces
+const OVERLAP_MS: u32 = 8;
+
+// the search first tries every this many frames, then refines around the best
+const COARSE_STEP: usize = 4;
+
+// Changes the tempo without changing the pitch, with WSOLA: the input is cut
+// into overlapping pieces that are taken further apart (faster) or closer
+// together (slower) than they are put back, each one shifted a little to
+// match the waveform it is crossfaded into.
+pub struct TimeStretch {
+    channels: usize,
+    frequency: u32,
+    // input not fully consumed yet, interleaved
+    input: Vec<f32>,
+    overlap: usize,
+    // where the next piece starts nominally, in frames into `input`
+    position: f64,
+    search: usize,
+    sequence: usize,
+    speed: f64,
+    // the end of the last piece, crossfaded into the next one
+    tail: Vec<f32>,
+}
+
+impl TimeStretch {
+    pub fn new(channels: usize, frequency: u32) -> TimeStretch {
+        let frames = |ms: u32| (frequency as u64 * ms as u64 / 1000).max(1) as usize;
+        TimeStretch {
+            channels,
+            frequency,
+            input: Vec::new(),
+            overlap: frames(OVERLAP_MS),
+            position: 0.0,
+            search: frames(SEARCH_MS),
+            sequence: frames(SEQUENCE_MS).max(frames(OVERLAP_MS) * 2),
+            speed: 1.0,
+            tail: Vec::new(),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    // 2.0 plays twice as fast, the output is half as long as the input
+    pub fn set_speed(&mut self, speed: f64) {
+        if speed != self.speed {
+            // a fast speed may have planned to skip input that isn't here
+            // yet, the new one starts from what there is
+            let frames = (self.input.len() / self.channels) as f64;
+            self.position = self.position.min(frames);
+            self.speed = speed;
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.position = 0.0;
+        self.tail.clear();
+    }
+
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        let (sequence, overlap) = (self.sequence, self.overlap);
+        self.input
+            .extend_from_slice(&input[..input.len() / channels * channels]);
+
+        while self.input.len() / channels >= self.position as usize + self.search + sequence {
+            let start = self.position as usize;
+            let start = if self.tail.is_empty() {
+                start
+            } else {
+                start + self.best_offset(start)
+            };
+            let piece = &self.input[start * channels..(start + sequence) * channels];
+
+            if self.tail.is_empty() {
+                output.extend_from_slice(&piece[..overlap * channels]);
+            } else {
+                // the pieces are lined up, so a linear fade keeps the level
+                for (frame, (tail, head)) in self
+                    .tail
+                    .chunks_exact(channels)
+                    .zip(piece.chunks_exact(channels))
+                    .enumerate()
+                {
+                    let fade = (frame as f32 + 0.5) / overlap as f32;
+                    output.extend(
+                        tail.iter()
+                            .zip(head)
+                            .map(|(&tail, &head)| tail + (head - tail) * fade),
+                    );
+                }
+            }
+            output.extend_from_slice(&piece[overlap * channels..(sequence - overlap) * channels]);
+
+            self.tail.clear();
+            self.tail
+                .extend_from_slice(&piece[(sequence - overlap) * channels..]);
+            self.position += (sequence - overlap) as f64 * self.speed;
+        }
+
+        let consumed = (self.position as usize).min(self.input.len() / channels);
+        self.input.drain(..consumed * channels);
+        self.position -= consumed as f64;
+    }
+
+    // hands out everything held back and starts over, the rest of the input
+    // follows the last piece as it is, so the stretch can be left without
+    // dropping audio
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let channels = self.channels;
+        let rest = &self.input[self.flush_start() * channels..];
+
+        let overlap = (self.tail.len() / channels).min(rest.len() / channels);
+        for (frame, (tail, head)) in self
+            .tail
+            .chunks_exact(channels)
+            .zip(rest.chunks_exact(channels))
+            .enumerate()
+        {
+            let fade = (frame as f32 + 0.5) / overlap as f32;
+            output.extend(
+                tail.iter()
+                    .zip(head)
+                    .map(|(&tail, &head)| tail + (head - tail) * fade),
+            );
+        }
+        output.extend_from_slice(&rest[overlap * channels..]);
+
+        self.reset();
+    }
+
+    // frames held back, as many as `flush` would hand out
+    pub fn latency(&self) -> usize {
+        self.input.len() / self.channels - self.flush_start()
+    }
+
+    // where the input `flush` hands out starts, lined up with the tail
+    fn flush_start(&self) -> usize {
+        let frames = self.input.len() / self.channels;
+        // the tail fades into the last of the input rather than into silence
+        // when there is little left
+        let start = (self.position as usize).min(frames.saturating_sub(self.search + self.overlap));
+
+        if !self.tail.is_empty() && frames >= start + self.search + self.overlap {
+            start + self.best_offset(start)
+        } else {
+            start
+        }
+    }
+
+    // the shift within the search window that matches the tail best
+    fn best_offset(&self, start: usize) -> usize {
+        let score = |offset: usize| {
+            let begin = (start + offset) * self.channels;
+            let candidate = &self.input[begin..begin + self.tail.len()];
+            let (correlation, energy) = self.tail.iter().zip(candidate).fold(
+                (0.0, 0.0),
+                |(correlation, energy), (&tail, &sample)| {
+                    (correlation + tail * sample, energy + sample * sample)
+                },
+            );
+            correlation / (energy + 1e-9f32).sqrt()
+        };
+        let best = |offsets: &mut dyn Iterator<Item = usize>| {
+            offsets
+                .map(|offset| (offset, score(offset)))
+                .fold(
+                    (0, f32::MIN),
+                    |best, it| if it.1 > best.1 { it } else { best },
+                )
+                .0
+        };
+
+        let coarse = best(&mut (0..self.search).step_by(COARSE_STEP));
+        let low = coarse.saturating_sub(COARSE_STEP - 1);
+        let high = (coarse + COARSE_STEP).min(self.search);
+        best(&mut (low..high))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const FREQUENCY: u32 = 48000;
+
+    fn tone(frequency: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|frame| (2.0 * PI * frequency * frame as f64 / FREQUENCY as f64).sin() as f32)
+            .collect()
+    }
+
+    // feeds `input` in blocks like `Audio` would and flushes at the end
+    fn stretch(input: &[f32], speed: f64) -> Vec<f32> {
+        let mut stretch = TimeStretch::new(1, FREQUENCY);
+        stretch.set_speed(speed);
+        let mut output = Vec::new();
+        for block in input.chunks(1024) {
+            stretch.process(block, &mut output);
+        }
+        stretch.flush(&mut output);
+        output
+    }
+
+    // from the zero crossings, skipping both ends
+    fn pitch(samples: &[f32]) -> f64 {
+        let samples = &samples[samples.len() / 4..samples.len() * 3 / 4];
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        crossings as f64 / 2.0 / (samples.len() as f64 / FREQUENCY as f64)
+    }
+
+    #[test]
+    fn output_length_follows_speed() {
+        let input = tone(440.0, FREQUENCY as usize * 4);
+        let held = TimeStretch::new(1, FREQUENCY);
+        // what `flush` hands out at the end isn't stretched
+        let tolerance = (held.sequence + held.search + held.overlap) as f64;
+
+        for speed in [0.5, 2.0, 8.0] {
+            let output = stretch(&input, speed);
+            let expected = input.len() as f64 / speed;
+            assert!(
+                (output.len() as f64 - expected).abs() < tolerance,
+                "{}x: {} frames instead of {}",
+                speed,
+                output.len(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn flush_hands_out_the_latency() {
+        let input = tone(440.0, FREQUENCY as usize);
+
+        for speed in [0.5, 1.0, 2.0, 8.0] {
+            let mut stretch = TimeStretch::new(1, FREQUENCY);
+            stretch.set_speed(speed);
+            let mut output = Vec::new();
+            stretch.process(&input[..12345], &mut output);
+
+            let latency = stretch.latency();
+            output.clear();
+            stretch.flush(&mut output);
+            assert_eq!(output.len(), latency, "{}x", speed);
+
+            // nothing is left to come out later
+            assert_eq!(stretch.latency(), 0);
+            output.clear();
+            stretch.flush(&mut output);
+            assert!(output.is_empty());
+        }
+    }
+
+    #[test]
+    fn keeps_the_pitch() {
+        for frequency in [220.0, 440.0, 1000.0] {
+            let input = tone(frequency, FREQUENCY as usize * 2);
+            for speed in [0.5, 2.0, 8.0] {
+                let output = stretch(&input, speed);
+                let pitch = pitch(&output);
+                assert!(
+                    (pitch / frequency - 1.0).abs() < 0.01,
+                    "{} Hz at {}x came out at {} Hz",
+                    frequency,
+                    speed,
+                    pitch
+                );
+            }
+        }
+    }
+}
@@ -1,52 +1,76 @@
 use core::fmt;
-use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use windows::core::w;
 use windows::core::PCWSTR;
+use windows::Win32::Devices::FunctionDiscovery::PKEY_DeviceInterface_FriendlyName;
 use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
 use windows::Win32::Foundation::CloseHandle;
 use windows::Win32::Foundation::HANDLE;
+use windows::Win32::Foundation::RPC_E_CHANGED_MODE;
 use windows::Win32::Foundation::WAIT_OBJECT_0;
 use windows::Win32::Media::Audio::eConsole;
 use windows::Win32::Media::Audio::eRender;
 use windows::Win32::Media::Audio::IAudioClient;
+use windows::Win32::Media::Audio::IAudioClock;
 use windows::Win32::Media::Audio::IAudioRenderClient;
 use windows::Win32::Media::Audio::IMMDevice;
 use windows::Win32::Media::Audio::IMMDeviceEnumerator;
 use windows::Win32::Media::Audio::MMDeviceEnumerator;
 use windows::Win32::Media::Audio::PKEY_AudioEngine_DeviceFormat;
 use windows::Win32::Media::Audio::AUDCLNT_BUFFERFLAGS_SILENT;
+use windows::Win32::Media::Audio::AUDCLNT_E_BUFFER_SIZE_ERROR;
+use windows::Win32::Media::Audio::AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED;
+use windows::Win32::Media::Audio::AUDCLNT_E_DEVICE_INVALIDATED;
+use windows::Win32::Media::Audio::AUDCLNT_E_DEVICE_IN_USE;
+use windows::Win32::Media::Audio::AUDCLNT_E_EXCLUSIVE_MODE_NOT_ALLOWED;
+use windows::Win32::Media::Audio::AUDCLNT_E_INVALID_DEVICE_PERIOD;
+use windows::Win32::Media::Audio::AUDCLNT_E_UNSUPPORTED_FORMAT;
 use windows::Win32::Media::Audio::AUDCLNT_SHAREMODE_EXCLUSIVE;
 use windows::Win32::Media::Audio::AUDCLNT_SHAREMODE_SHARED;
 use windows::Win32::Media::Audio::AUDCLNT_STREAMFLAGS_EVENTCALLBACK;
 use windows::Win32::Media::Audio::DEVICE_STATE_ACTIVE;
 use windows::Win32::Media::Audio::WAVEFORMATEXTENSIBLE;
 use windows::Win32::System::Com::CoCreateInstance;
+use windows::Win32::System::Com::CoInitializeEx;
 use windows::Win32::System::Com::CoTaskMemFree;
+use windows::Win32::System::Com::CoUninitialize;
 use windows::Win32::System::Com::CLSCTX_ALL;
+use windows::Win32::System::Com::COINIT_MULTITHREADED;
 use windows::Win32::System::Com::STGM_READ;
+use windows::Win32::System::Performance::QueryPerformanceCounter;
+use windows::Win32::System::Performance::QueryPerformanceFrequency;
 use windows::Win32::System::Threading::AvRevertMmThreadCharacteristics;
 use windows::Win32::System::Threading::AvSetMmThreadCharacteristicsW;
 use windows::Win32::System::Threading::CreateEventW;
 use windows::Win32::System::Threading::WaitForSingleObject;
-use windows::Win32::System::Threading::INFINITE;
+use windows::Win32::System::Variant::VT_BLOB;
+use windows::Win32::System::Variant::VT_LPWSTR;
+use windows::Win32::UI::Shell::PropertiesSystem::IPropertyStore;
+use windows::Win32::UI::Shell::PropertiesSystem::PROPERTYKEY;
 
-use super::AudioDriver;
+use super::{
+    ring_buffer, sample, AudioClock, AudioConfig, AudioDriver, AudioStats, Consumer,
+    DeviceCapabilities, DeviceInfo, DeviceSource, ErrorKind, Producer, SampleFormat, Speaker,
+    StreamFormat,
+};
 
 pub enum Error {
+    // the stream let go of the endpoint and couldn't get it back
+    Closed,
     DeviceNotFound(String),
-    FromUtf16Error(std::string::FromUtf16Error),
-    WaitTimeout,
-    WindowsError(windows::core::Error),
+    FromUtf16(std::string::FromUtf16Error),
+    Windows(windows::core::Error),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Error::Closed => write!(f, "Stream closed"),
             Error::DeviceNotFound(name) => write!(f, "Device not found: {}", name),
-            Error::FromUtf16Error(error) => write!(f, "FromUtf16Error: {}", error),
-            Error::WaitTimeout => write!(f, "WaitTimeout"),
-            Error::WindowsError(error) => write!(f, "WindowsError: {}", error),
+            Error::FromUtf16(error) => write!(f, "FromUtf16Error: {}", error),
+            Error::Windows(error) => write!(f, "WindowsError: {}", error),
         }
     }
 }
@@ -54,41 +78,173 @@ impl fmt::Display for Error {
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Error::Closed => write!(f, "Closed"),
             Error::DeviceNotFound(name) => write!(f, "DeviceNotFound({})", name),
-            Error::FromUtf16Error(error) => write!(f, "FromUtf16Error({:?})", error),
-            Error::WaitTimeout => write!(f, "WaitTimeout"),
-            Error::WindowsError(error) => write!(f, "WindowsError({:?})", error),
+            Error::FromUtf16(error) => write!(f, "FromUtf16({:?})", error),
+            Error::Windows(error) => write!(f, "Windows({:?})", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::FromUtf16(error) => Some(error),
+            Error::Windows(error) => Some(error),
+            _ => None,
         }
     }
 }
 
 impl From<std::string::FromUtf16Error> for Error {
     fn from(error: std::string::FromUtf16Error) -> Self {
-        Error::FromUtf16Error(error)
+        Error::FromUtf16(error)
     }
 }
 
 impl From<windows::core::Error> for Error {
     fn from(error: windows::core::Error) -> Self {
-        Error::WindowsError(error)
+        Error::Windows(error)
     }
 }
 
-struct WASAPIDriverPrev {
+impl From<Error> for super::Error {
+    fn from(err: Error) -> Self {
+        let kind = match &err {
+            // the device was in our list, so it went away since
+            Error::Closed | Error::DeviceNotFound(_) => ErrorKind::DeviceLost,
+            Error::Windows(error) => match error.code() {
+                AUDCLNT_E_DEVICE_INVALIDATED => ErrorKind::DeviceLost,
+                AUDCLNT_E_DEVICE_IN_USE => ErrorKind::DeviceBusy,
+                AUDCLNT_E_UNSUPPORTED_FORMAT => ErrorKind::FormatUnsupported,
+                AUDCLNT_E_BUFFER_SIZE_ERROR
+                | AUDCLNT_E_BUFFER_SIZE_NOT_ALIGNED
+                | AUDCLNT_E_EXCLUSIVE_MODE_NOT_ALLOWED
+                | AUDCLNT_E_INVALID_DEVICE_PERIOD => ErrorKind::InvalidConfig,
+                _ => ErrorKind::Backend,
+            },
+            _ => ErrorKind::Backend,
+        };
+        super::Error::with_source(kind, err)
+    }
+}
+
+// the format the endpoint runs at, which is what exclusive mode has to use
+fn device_format(device: &IMMDevice) -> Result<WAVEFORMATEXTENSIBLE, Error> {
+    let property_store = unsafe { device.OpenPropertyStore(STGM_READ) }?;
+    let property_variant = unsafe { property_store.GetValue(&PKEY_AudioEngine_DeviceFormat) }?;
+    let variant = unsafe { &property_variant.Anonymous.Anonymous };
+    if variant.vt != VT_BLOB {
+        return Err(windows::core::Error::from(AUDCLNT_E_UNSUPPORTED_FORMAT).into());
+    }
+
+    // the blob may hold a plain WAVEFORMATEX, the rest stays zeroed then
+    let blob = unsafe { variant.Anonymous.blob };
+    let mut wave_format = WAVEFORMATEXTENSIBLE::default();
+    let size = (blob.cbSize as usize).min(std::mem::size_of::<WAVEFORMATEXTENSIBLE>());
+    unsafe {
+        std::ptr::copy_nonoverlapping(blob.pBlobData, &mut wave_format as *mut _ as *mut u8, size)
+    };
+    Ok(wave_format)
+}
+
+fn sample_format(wave_format: &WAVEFORMATEXTENSIBLE) -> Option<SampleFormat> {
+    let tag = match wave_format.Format.wFormatTag {
+        0xfffe => wave_format.SubFormat.data1, // WAVE_FORMAT_EXTENSIBLE
+        tag => tag as u32,
+    };
+    let valid_bits = unsafe { wave_format.Samples.wValidBitsPerSample };
+    match (tag, wave_format.Format.wBitsPerSample) {
+        (1, 8) => Some(SampleFormat::U8),
+        (1, 16) => Some(SampleFormat::S16),
+        (1, 32) if valid_bits == 24 => Some(SampleFormat::S24),
+        (1, 32) => Some(SampleFormat::S32),
+        (3, 32) => Some(SampleFormat::F32),
+        (3, 64) => Some(SampleFormat::F64),
+        _ => None,
+    }
+}
+
+// `Instant` counts on the performance counter too, so the qpc time a position
+// was taken at, in 100 ns units, lands on it by its distance from now
+fn instant(qpc: u64) -> Option<Instant> {
+    let (mut counter, mut frequency) = (0i64, 0i64);
+    unsafe {
+        QueryPerformanceCounter(&mut counter).ok()?;
+        QueryPerformanceFrequency(&mut frequency).ok()?;
+    }
+    if qpc == 0 || frequency <= 0 {
+        return None;
+    }
+
+    let now = counter as i128 * 10_000_000 / frequency as i128;
+    let age = (now - qpc as i128) * 100;
+    let instant = Instant::now();
+    if age >= 0 {
+        instant.checked_sub(Duration::from_nanos(age as u64))
+    } else {
+        instant.checked_add(Duration::from_nanos(-age as u64))
+    }
+}
+
+fn property_string(property_store: &IPropertyStore, key: &PROPERTYKEY) -> Result<String, Error> {
+    let property_variant = unsafe { property_store.GetValue(key) }?;
+    let variant = unsafe { &property_variant.Anonymous.Anonymous };
+    if variant.vt != VT_LPWSTR {
+        return Ok(String::new());
+    }
+    Ok(unsafe { variant.Anonymous.pwszVal.to_string() }?)
+}
+
+// The COM side of a stream, the endpoint is held until it is dropped.
+struct Client {
     audio_client: IAudioClient,
+    audio_clock: IAudioClock,
     _audio_device: IMMDevice,
+    event_handle: HANDLE,
+    render_client: IAudioRenderClient,
+    task_handle: Option<HANDLE>,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Err(err) = unsafe { self.audio_client.Stop() } {
+            eprintln!("IAudioClient::Stop failed: {:?}", err);
+        }
+
+        if let Err(err) = unsafe { CloseHandle(self.event_handle) } {
+            eprintln!("CloseHandle failed: {:?}", err);
+        }
+
+        if let Some(task_handle) = self.task_handle {
+            if let Err(err) = unsafe { AvRevertMmThreadCharacteristics(task_handle) } {
+                eprintln!("AvRevertMmThreadCharacteristics failed: {:?}", err);
+            }
+        }
+    }
+}
+
+struct WASAPIDriverPrev {
+    buffer: Vec<f32>,
     buffer_size: u32,
+    channel_mask: u32,
     channels: u16,
-    _device_period: i64,
-    event_handle: HANDLE,
+    // only empty after a switch of the sharing mode failed and the old
+    // stream couldn't be opened again, writing then reports the device lost
+    client: Option<Client>,
+    consumer: Consumer<f32>,
+    device_period: i64,
     exclusive: bool,
     frequency: u32,
+    format: Option<SampleFormat>,
     latency: i64,
-    mode: u32,
-    precision: u16,
-    render_client: IAudioRenderClient,
-    samples: VecDeque<Vec<f64>>,
-    task_handle: Option<HANDLE>,
+    // frames heard before the client's position last started from 0, on a
+    // reset or on the streams before this one
+    played: u64,
+    producer: Producer<f32>,
+    // something was written since the stream was last empty on purpose
+    started: bool,
+    stats: AudioStats,
 }
 
 impl WASAPIDriverPrev {
@@ -104,23 +260,8 @@ impl WASAPIDriverPrev {
         let wave_format: WAVEFORMATEXTENSIBLE;
 
         if exclusive {
-            let property_store = unsafe { audio_device.OpenPropertyStore(STGM_READ) }?;
-            let property_variant =
-                unsafe { property_store.GetValue(&PKEY_AudioEngine_DeviceFormat) }?;
-            wave_format = unsafe {
-                property_variant
-                    .Anonymous
-                    .Anonymous
-                    .Anonymous
-                    .blob
-                    .pBlobData
-                    .cast::<WAVEFORMATEXTENSIBLE>()
-                    .as_ref()
-                    .unwrap()
-                    .clone()
-            };
-
-            let mut device_period = 0i64;
+            wave_format = device_format(&audio_device)?;
+
             unsafe { audio_client.GetDevicePeriod(None, Some(&mut device_period))? };
 
             let latency = device_period.max(latency * 10_000); // 1ms -> 100 ns units
@@ -141,11 +282,13 @@ impl WASAPIDriverPrev {
         } else {
             let wave_format_ex = unsafe { audio_client.GetMixFormat()? };
             wave_format = unsafe { wave_format_ex.cast::<WAVEFORMATEXTENSIBLE>().as_ref() }
-                .unwrap()
-                .clone();
+                .copied()
+                .unwrap();
             unsafe { CoTaskMemFree(Some(wave_format_ex as *const _)) };
 
-            unsafe { audio_client.GetDevicePeriod(None, Some(&mut device_period))? };
+            // the engine runs shared streams at the default period, the
+            // minimum is only available to exclusive ones
+            unsafe { audio_client.GetDevicePeriod(Some(&mut device_period), None)? };
 
             let latency = device_period.max(latency * 10_000); // 1ms -> 100 ns units
             unsafe {
@@ -164,117 +307,137 @@ impl WASAPIDriverPrev {
         unsafe { audio_client.SetEventHandle(event_handle) }?;
 
         let render_client = unsafe { audio_client.GetService::<IAudioRenderClient>()? };
+        let audio_clock = unsafe { audio_client.GetService::<IAudioClock>()? };
         let buffer_size = unsafe { audio_client.GetBufferSize()? };
 
-        let samples = VecDeque::with_capacity(buffer_size as usize);
+        let samples = buffer_size as usize * wave_format.Format.nChannels as usize;
+        let (producer, consumer) = ring_buffer(samples);
 
         unsafe { audio_client.Reset()? };
         unsafe { audio_client.Start()? };
 
+        let extensible = wave_format.Format.wFormatTag == 0xfffe; // WAVE_FORMAT_EXTENSIBLE
+        let channel_mask = if extensible {
+            wave_format.dwChannelMask
+        } else {
+            0
+        };
+        let format = sample_format(&wave_format);
+
         Ok(WASAPIDriverPrev {
-            audio_client,
-            _audio_device: audio_device,
+            buffer: vec![0.0; samples],
             buffer_size,
+            channel_mask,
             channels: wave_format.Format.nChannels,
-            _device_period: device_period,
-            event_handle,
+            client: Some(Client {
+                audio_client,
+                audio_clock,
+                _audio_device: audio_device,
+                event_handle,
+                render_client,
+                task_handle,
+            }),
+            consumer,
+            device_period,
             exclusive,
             frequency: wave_format.Format.nSamplesPerSec,
+            format,
             latency,
-            mode: wave_format.SubFormat.data1,
-            precision: wave_format.Format.wBitsPerSample,
-            render_client,
-            samples,
-            task_handle,
+            played: 0,
+            producer,
+            started: false,
+            stats: AudioStats::default(),
         })
     }
 
+    fn client(&self) -> Result<&Client, Error> {
+        self.client.as_ref().ok_or(Error::Closed)
+    }
+
+    // the client's position in frames and the qpc time it was taken at
+    fn position(&self) -> Option<(u64, u64)> {
+        let audio_clock = &self.client().ok()?.audio_clock;
+        let clock_frequency = unsafe { audio_clock.GetFrequency() }.ok()?;
+        let (mut position, mut qpc) = (0u64, 0u64);
+        unsafe { audio_clock.GetPosition(&mut position, Some(&mut qpc)) }.ok()?;
+
+        // the clock counts in its own units, usually bytes per second
+        let frequency = self.frequency as u128;
+        let position = (position as u128 * frequency / clock_frequency as u128) as u64;
+        Some((position, qpc))
+    }
+
+    // frames the listener has heard, across resets
+    fn played(&self) -> u64 {
+        self.played + self.position().map_or(0, |(position, _)| position)
+    }
+
+    // lets go of the endpoint, the clock keeps what was heard so far
+    fn release(&mut self) {
+        self.played = self.played();
+        self.client = None;
+    }
+
     fn write(&mut self) -> Result<(), Error> {
+        let start = Instant::now();
+        let Some(client) = &self.client else {
+            return Err(Error::Closed);
+        };
         let available = if !self.exclusive {
-            let padding = unsafe { self.audio_client.GetCurrentPadding()? };
+            let padding = unsafe { client.audio_client.GetCurrentPadding()? };
+            // shared mode plays silence when it runs dry instead of failing
+            if padding == 0 && self.started {
+                self.stats.underruns += 1;
+            }
             self.buffer_size - padding
         } else {
             self.buffer_size
         };
-        let length = available.min(self.samples.len() as u32);
+        let channels = self.channels as usize;
+        let length = available.min((self.consumer.len() / channels) as u32);
 
-        let mut buffer = unsafe { self.render_client.GetBuffer(length) }?;
+        let buffer = unsafe { client.render_client.GetBuffer(length) }?;
         let mut buffer_flags = 0;
-        for _ in 0..length as usize {
-            let sample = self.samples.pop_front().unwrap();
-
-            if self.mode == 1 && self.precision == 16 {
-                let output = unsafe {
-                    std::slice::from_raw_parts_mut(buffer as *mut i16, self.channels as usize)
-                };
-                for (output, sample) in output.iter_mut().zip(sample.iter()) {
-                    *output = (sample * (32768.0 - 1.0)) as i16; // 2^15 - 1, i16::max as f64
-                }
-                buffer = unsafe { buffer.offset(self.channels as isize * 2) };
-            } else if self.mode == 1 && self.precision == 32 {
-                let output = unsafe {
-                    std::slice::from_raw_parts_mut(buffer as *mut i32, self.channels as usize)
-                };
-                for (output, sample) in output.iter_mut().zip(sample.iter()) {
-                    *output = (sample * (2147483648.0 - 1.0)) as i32; // 2^31 - 1, i32::max as f64
-                }
-                buffer = unsafe { buffer.offset(self.channels as isize * 4) };
-            } else if self.mode == 3 && self.precision == 32 {
-                let output = unsafe {
-                    std::slice::from_raw_parts_mut(buffer as *mut f32, self.channels as usize)
-                };
-                for (output, sample) in output.iter_mut().zip(sample.iter()) {
-                    *output = sample.min(1.0).max(-1.0) as f32;
-                }
-                buffer = unsafe { buffer.offset(self.channels as isize * 4) };
-            } else {
-                //output silence for unsupported sample formats
-                buffer_flags = AUDCLNT_BUFFERFLAGS_SILENT.0 as u32;
-                break;
-            }
+        if let Some(format) = self.format {
+            let count = self
+                .consumer
+                .pop_slice(&mut self.buffer[..length as usize * channels]);
+            let output = unsafe { std::slice::from_raw_parts_mut(buffer, count * format.bytes()) };
+            sample::write_samples(format, &self.buffer[..count], output);
+        } else {
+            //output silence for unsupported sample formats
+            self.consumer.consume(length as usize * channels);
+            buffer_flags = AUDCLNT_BUFFERFLAGS_SILENT.0 as u32;
         }
-        unsafe { self.render_client.ReleaseBuffer(length, buffer_flags) }?;
+        unsafe { client.render_client.ReleaseBuffer(length, buffer_flags) }?;
+        self.started = length > 0;
 
+        self.stats.max_write_time = self.stats.max_write_time.max(start.elapsed());
         Ok(())
     }
 
+    // leaves the stream stopped, the caller restarts it unless paused
     fn clear(&mut self) -> Result<(), Error> {
-        self.samples.clear();
+        self.consumer.clear();
+        self.started = false;
+        // Reset starts the position from 0
+        self.played = self.played();
+        let client = self.client()?;
         unsafe {
-            self.audio_client.Stop()?;
-            self.audio_client.Reset()?;
-            self.audio_client.Start()?;
+            client.audio_client.Stop()?;
+            client.audio_client.Reset()?;
         }
         Ok(())
     }
 }
 
-impl Drop for WASAPIDriverPrev {
-    fn drop(&mut self) {
-        if let Err(err) = unsafe { self.audio_client.Stop() } {
-            eprintln!("IAudioClient::Stop failed: {:?}", err);
-        }
-
-        if let Err(err) = unsafe { CloseHandle(self.event_handle) } {
-            eprintln!("CloseHandle failed: {:?}", err);
-        }
-
-        if let Some(task_handle) = self.task_handle {
-            match unsafe { AvRevertMmThreadCharacteristics(task_handle) } {
-                Err(err) => eprintln!("AvRevertMmThreadCharacteristics failed: {:?}", err),
-                _ => {}
-            };
-        }
-    }
-}
-
 pub struct WASAPIDriver {
     prev: WASAPIDriverPrev,
-    current_device_name: String,
-    device_names: Vec<String>,
-    device_ids: Vec<String>,
+    current_device_id: String,
+    devices: Vec<DeviceInfo>,
     enumlator: IMMDeviceEnumerator,
     blocking: bool,
+    paused: bool,
 }
 
 fn str_to_pcwstr(s: &str) -> Vec<u16> {
@@ -286,11 +449,112 @@ fn str_to_pcwstr(s: &str) -> Vec<u16> {
     result
 }
 
-impl WASAPIDriver {
-    pub fn driver() -> &'static str {
-        "WASAPI"
+// active render endpoints with the default one first, if there is one
+fn enumerate(enumlator: &IMMDeviceEnumerator) -> Result<Vec<DeviceInfo>, Error> {
+    // there is no default while the last device is unplugged
+    let default_device_id = unsafe { enumlator.GetDefaultAudioEndpoint(eRender, eConsole) }
+        .ok()
+        .and_then(|device| unsafe { device.GetId().ok()?.to_string().ok() });
+
+    let device_collection = unsafe { enumlator.EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)? };
+    let count = unsafe { device_collection.GetCount()? };
+
+    let mut devices = Vec::with_capacity(count as usize);
+
+    for i in 0..count {
+        let device_context = unsafe { device_collection.Item(i) }?;
+        let id = unsafe { device_context.GetId()?.to_string()? };
+        let property_store = unsafe { device_context.OpenPropertyStore(STGM_READ) }?;
+        let name = property_string(&property_store, &PKEY_Device_FriendlyName)?;
+        let description = property_string(&property_store, &PKEY_DeviceInterface_FriendlyName)?;
+
+        // shared mode converts to the mix format, so this is what the
+        // hardware itself is set to
+        let capabilities = device_format(&device_context)
+            .map(|wave_format| {
+                let channels = wave_format.Format.nChannels as u32;
+                let frequency = wave_format.Format.nSamplesPerSec;
+                DeviceCapabilities {
+                    channels: vec![channels],
+                    frequencies: vec![frequency],
+                    formats: sample_format(&wave_format).into_iter().collect(),
+                    channel_range: Some(channels..=channels),
+                    frequency_range: Some(frequency..=frequency),
+                    ..DeviceCapabilities::default()
+                }
+            })
+            .unwrap_or_default();
+
+        let device = DeviceInfo {
+            is_default: default_device_id.as_deref() == Some(id.as_str()),
+            id,
+            name,
+            description,
+            capabilities,
+        };
+        if device.is_default {
+            devices.insert(0, device);
+        } else {
+            devices.push(device);
+        }
+    }
+
+    Ok(devices)
+}
+
+// Lists the endpoints again about once a second. COM objects can't move to the
+// watcher thread, so every listing sets up its own enumerator.
+pub struct WASAPIDeviceSource {
+    last: Option<Instant>,
+}
+
+impl WASAPIDeviceSource {
+    pub fn new() -> WASAPIDeviceSource {
+        WASAPIDeviceSource { last: None }
+    }
+}
+
+impl Default for WASAPIDeviceSource {
+    fn default() -> Self {
+        WASAPIDeviceSource::new()
+    }
+}
+
+impl DeviceSource for WASAPIDeviceSource {
+    fn devices(&mut self) -> Result<Vec<DeviceInfo>, super::Error> {
+        // the first listing runs on the caller's thread, which may already
+        // be set up as a single-threaded apartment
+        let initialized = match unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) } {
+            Ok(()) => true,
+            Err(err) if err.code() == RPC_E_CHANGED_MODE => false,
+            Err(err) => return Err(Error::from(err).into()),
+        };
+        let devices = unsafe {
+            CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)
+        }
+        .map_err(Error::from)
+        .and_then(|enumlator| enumerate(&enumlator));
+        if initialized {
+            unsafe { CoUninitialize() };
+        }
+        Ok(devices?)
+    }
+
+    fn wait(&mut self, timeout: Duration) -> bool {
+        thread::sleep(timeout);
+        let now = Instant::now();
+        if self
+            .last
+            .is_some_and(|last| now - last < Duration::from_secs(1))
+        {
+            return false;
+        }
+        self.last = Some(now);
+        true
     }
+}
 
+impl WASAPIDriver {
     pub fn new() -> Result<Self, Error> {
         let enumlator = unsafe {
             CoCreateInstance::<_, IMMDeviceEnumerator>(&MMDeviceEnumerator, None, CLSCTX_ALL)?
@@ -299,75 +563,113 @@ impl WASAPIDriver {
         let audio_device = unsafe { enumlator.GetDefaultAudioEndpoint(eRender, eConsole)? };
 
         let default_device_id = unsafe { audio_device.GetId()?.to_string()? };
-
-        let device_collection =
-            unsafe { enumlator.EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)? };
-        let count = unsafe { device_collection.GetCount()? };
-
-        let mut device_names = Vec::with_capacity(count as usize);
-        let mut device_ids = Vec::with_capacity(count as usize);
-
-        for i in 0..count {
-            let device_context = unsafe { device_collection.Item(i) }?;
-            let id = unsafe { device_context.GetId()?.to_string()? };
-            let property_store = unsafe { device_context.OpenPropertyStore(STGM_READ) }?;
-            let property_variant = unsafe { property_store.GetValue(&PKEY_Device_FriendlyName) }?;
-            let name = unsafe {
-                property_variant
-                    .Anonymous
-                    .Anonymous
-                    .Anonymous
-                    .pwszVal
-                    .to_string()
-            }?;
-
-            if id == default_device_id {
-                device_ids.insert(0, id);
-                device_names.insert(0, name);
-            } else {
-                device_ids.push(id);
-                device_names.push(name);
-            }
-        }
+        let devices = enumerate(&enumlator)?;
 
         let prev = WASAPIDriverPrev::new(audio_device, false, 40)?;
 
         Ok(WASAPIDriver {
             prev,
-            current_device_name: device_names[0].clone(),
-            device_names,
-            device_ids,
+            current_device_id: default_device_id,
+            devices,
             enumlator,
             blocking: true,
+            paused: false,
         })
     }
 
-    pub fn reset(&mut self) -> Result<(), Error> {
-        let device_id: &str = &self
-            .device_ids
-            .iter()
-            .zip(self.device_names.iter())
-            .find_map(|(id, name)| {
-                if **name == self.current_device_name {
-                    Some(id)
-                } else {
-                    None
-                }
-            })
-            .ok_or(Error::DeviceNotFound(self.current_device_name.clone()))?;
-
+    fn open(&self, id: &str, exclusive: bool, latency: i64) -> Result<WASAPIDriverPrev, Error> {
+        let device_id = str_to_pcwstr(id);
         let device = unsafe {
             self.enumlator
-                .GetDevice(PCWSTR::from_raw(str_to_pcwstr(device_id).as_ptr()))?
+                .GetDevice(PCWSTR::from_raw(device_id.as_ptr()))
+        }
+        .map_err(|_| Error::DeviceNotFound(id.to_string()))?;
+
+        let prev = WASAPIDriverPrev::new(device, exclusive, latency)?;
+        if self.paused {
+            unsafe { prev.client()?.audio_client.Stop()? };
+        }
+        Ok(prev)
+    }
+
+    // swaps in a new stream, the clock goes on from what the listener heard
+    // on the old one, converted in case the rate changed
+    fn replace(&mut self, mut prev: WASAPIDriverPrev) {
+        let played = self.prev.played() as u128;
+        prev.played = (played * prev.frequency as u128 / self.prev.frequency as u128) as u64;
+        self.prev = prev;
+    }
+
+    // opens the old settings again after `configure` released the endpoint
+    // for a new stream that didn't work out, then hands back `err`
+    fn restore(&mut self, released: bool, err: super::Error) -> super::Error {
+        if released {
+            let id = self.current_device_id.clone();
+            let _ = self.reopen(Some(&id));
+        }
+        err
+    }
+
+    fn default_device_id(&self) -> Result<String, Error> {
+        let device = unsafe { self.enumlator.GetDefaultAudioEndpoint(eRender, eConsole)? };
+        Ok(unsafe { device.GetId()?.to_string()? })
+    }
+
+    // Whether the device has room for a write. Shared mode sizes its writes by
+    // the padding, so only exclusive mode needs the event when not blocking.
+    // An invalidated stream stops signaling its event, so a blocking wait
+    // wakes up every few buffers and asks the client, which then reports
+    // AUDCLNT_E_DEVICE_INVALIDATED instead of hanging.
+    fn wait(&mut self, blocking: bool) -> Result<bool, super::Error> {
+        if !blocking && !self.prev.exclusive {
+            return Ok(true);
+        }
+        let timeout = if blocking {
+            let buffer_ms = self.prev.buffer_size as u64 * 1000 / self.prev.frequency as u64;
+            (buffer_ms * 4).max(20) as u32
+        } else {
+            0
         };
 
-        self.prev = WASAPIDriverPrev::new(device, self.prev.exclusive, self.prev.latency)?;
+        let start = Instant::now();
+        let result = loop {
+            let client = self.prev.client()?;
+            let result = unsafe { WaitForSingleObject(client.event_handle, timeout) };
+            if result == WAIT_OBJECT_0 || !blocking {
+                break Ok(result == WAIT_OBJECT_0);
+            }
+            if let Err(err) = unsafe { client.audio_client.GetCurrentPadding() } {
+                break Err(err);
+            }
+        };
+        self.prev.stats.blocking_time += start.elapsed();
 
-        Ok(())
+        Ok(result.map_err(Error::from)?)
     }
 
-    pub fn clear(&mut self) -> Result<(), Error> {
-        self.prev.clear()
+    fn queue(&mut self, mut samples: &[f32]) -> Result<(), super::Error> {
+        let channels = self.prev.channels as usize;
+        let threshold = self.prev.buffer_size as usize * channels;
+
+        loop {
+            let count = self.prev.producer.push_slice(samples);
+            samples = &samples[count..];
+
+            let queued = self.prev.consumer.len();
+            if queued >= threshold && self.wait(self.blocking)? {
+                self.prev.write()?;
+            }
+
+            if samples.is_empty() {
+                return Ok(());
+            }
+            // without blocking, what the device had no room for is dropped
+            // rather than waited for
+            if !self.blocking && self.prev.consumer.len() == queued {
+                self.prev.stats.dropped_frames += (samples.len() / channels) as u64;
+                return Ok(());
+            }
+        }
     }
 }
 
@@ -380,8 +682,8 @@ impl AudioDriver for WASAPIDriver {
         true
     }
 
-    fn support_device_list(&self) -> Vec<String> {
-        self.device_names.clone()
+    fn support_device_list(&self) -> Vec<DeviceInfo> {
+        self.devices.clone()
     }
 
     fn support_blocking(&self) -> bool {
@@ -401,86 +703,312 @@ impl AudioDriver for WASAPIDriver {
     }
 
     fn set_exclusive(&mut self, exclusive: bool) -> Result<(), super::Error> {
-        if self.prev.exclusive == exclusive {
-            return Ok(());
-        }
-
-        self.prev.exclusive = exclusive;
-        self.reset()?;
+        self.configure(&AudioConfig::new().exclusive(exclusive))?;
         Ok(())
     }
 
     fn set_device(&mut self, device: &str) -> Result<(), super::Error> {
-        if self.current_device_name == device {
-            return Ok(());
-        }
-
-        self.current_device_name = device.to_owned();
-        self.reset()?;
+        self.configure(&AudioConfig::new().device(device))?;
         Ok(())
     }
 
     fn set_blocking(&mut self, blocking: bool) -> Result<(), super::Error> {
-        if self.blocking == blocking {
-            return Ok(());
-        }
-
-        self.blocking = blocking;
+        self.configure(&AudioConfig::new().blocking(blocking))?;
         Ok(())
     }
 
     fn set_latency(&mut self, latency: u32) -> Result<(), super::Error> {
-        if self.prev.latency == latency as i64 {
-            return Ok(());
+        self.configure(&AudioConfig::new().latency(latency))?;
+        Ok(())
+    }
+
+    fn configure(&mut self, config: &AudioConfig) -> Result<AudioConfig, super::Error> {
+        let invalid = |what: String| super::Error::new(ErrorKind::InvalidConfig, what);
+
+        let device = config.device.as_ref().unwrap_or(&self.current_device_id);
+        if !self.devices.iter().any(|it| it.id == *device) {
+            return Err(invalid(format!("Device {} not found", device)));
+        }
+        if let Some(latency) = config.latency.filter(|&it| !self.test_latency(it)) {
+            return Err(invalid(format!("latency: {}", latency)));
+        }
+
+        let device = device.clone();
+        let exclusive = config.exclusive.unwrap_or(self.prev.exclusive);
+        let latency = config.latency.map_or(self.prev.latency, i64::from);
+
+        let reopen = device != self.current_device_id
+            || exclusive != self.prev.exclusive
+            || latency != self.prev.latency;
+        // an endpoint can't be opened exclusively while this stream holds it
+        // in shared mode, and the other way around, so the old stream goes
+        // first and comes back with its old settings if the new one fails
+        let release = device == self.current_device_id && exclusive != self.prev.exclusive;
+        if release {
+            self.prev.release();
+        }
+        let prev = if reopen {
+            match self.open(&device, exclusive, latency) {
+                Ok(prev) => Some(prev),
+                Err(err) => return Err(self.restore(release, err.into())),
+            }
+        } else {
+            None
+        };
+
+        // rate, channels and format follow the device, so they can only be
+        // checked on the new stream, the old one keeps playing if they're off
+        let check = |stream: &WASAPIDriverPrev| {
+            if let Some(frequency) = config.frequency.filter(|&it| it != stream.frequency) {
+                return Err(invalid(format!("frequency: {}", frequency)));
+            }
+            if let Some(channels) = config.channels.filter(|&it| it != stream.channels as u32) {
+                return Err(invalid(format!("channels: {}", channels)));
+            }
+            if let Some(format) = config.format.filter(|&it| Some(it) != stream.format) {
+                return Err(super::Error::new(
+                    ErrorKind::FormatUnsupported,
+                    format.to_string(),
+                ));
+            }
+            Ok(())
+        };
+        if let Err(err) = check(prev.as_ref().unwrap_or(&self.prev)) {
+            drop(prev);
+            return Err(self.restore(release, err));
         }
 
-        self.prev.latency = latency as i64;
-        self.reset()?;
+        if let Some(prev) = prev {
+            self.replace(prev);
+            self.current_device_id = device;
+        }
+        if let Some(blocking) = config.blocking {
+            self.blocking = blocking;
+        }
+
+        Ok(self.config())
+    }
+
+    fn config(&self) -> AudioConfig {
+        AudioConfig {
+            device: Some(self.current_device_id.clone()),
+            frequency: Some(self.prev.frequency),
+            channels: Some(self.prev.channels as u32),
+            format: self.prev.format,
+            latency: Some(self.prev.latency as u32),
+            blocking: Some(self.blocking),
+            exclusive: Some(self.prev.exclusive),
+        }
+    }
+
+    fn current_format(&self) -> StreamFormat {
+        let frequency = self.prev.frequency;
+        // an exclusive stream hands over the whole buffer every period
+        let period_size = if self.prev.exclusive {
+            self.prev.buffer_size as usize
+        } else {
+            let period = self.prev.device_period as u64; // 100 ns units
+            (period * frequency as u64 / 10_000_000) as usize
+        };
+
+        StreamFormat {
+            frequency,
+            channels: self.prev.channels as u32,
+            format: self.sample_format(),
+            period_size,
+            buffer_size: self.prev.buffer_size as usize,
+        }
+    }
+
+    fn channels(&self) -> u32 {
+        self.prev.channels as u32
+    }
+
+    fn frequency(&self) -> u32 {
+        self.prev.frequency
+    }
+
+    fn speakers(&self) -> Vec<Speaker> {
+        use Speaker::*;
+
+        // SPEAKER_* bits in ksmedia.h order, which is also the sample order
+        const POSITIONS: [Speaker; 11] = [
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+            Other, // front left of center
+            Other, // front right of center
+            Other, // back center
+            SideLeft,
+            SideRight,
+        ];
+
+        let channels = self.prev.channels as usize;
+        let mask = self.prev.channel_mask;
+        let mut speakers = (0..32)
+            .filter(|bit| mask & (1 << bit) != 0)
+            .map(|bit| POSITIONS.get(bit).copied().unwrap_or(Other))
+            .collect::<Vec<_>>();
+
+        if speakers.len() > channels || mask == 0 {
+            return super::channel::default_speakers(channels as u32);
+        }
+        speakers.resize(channels, Other);
+        speakers
+    }
+
+    fn sample_format(&self) -> SampleFormat {
+        self.prev.format.unwrap_or(SampleFormat::F32)
+    }
+
+    fn queued_frames(&self) -> usize {
+        let padding = self
+            .prev
+            .client()
+            .and_then(|client| Ok(unsafe { client.audio_client.GetCurrentPadding()? }))
+            .unwrap_or(0);
+        padding as usize + self.prev.consumer.len() / self.prev.channels as usize
+    }
+
+    fn buffer_capacity_frames(&self) -> usize {
+        self.prev.buffer_size as usize * 2
+    }
+
+    fn estimated_latency(&self) -> Duration {
+        let stream = self
+            .prev
+            .client()
+            .and_then(|client| Ok(unsafe { client.audio_client.GetStreamLatency()? }))
+            .unwrap_or(0);
+        let queued = self.queued_frames() as f64 / self.prev.frequency as f64;
+        Duration::from_secs_f64(queued) + Duration::from_nanos(stream.max(0) as u64 * 100)
+    }
+
+    fn clock(&self) -> Option<AudioClock> {
+        let (position, qpc) = self.prev.position()?;
+        Some(AudioClock {
+            position: self.prev.played + position,
+            frequency: self.prev.frequency,
+            timestamp: instant(qpc).unwrap_or_else(Instant::now),
+        })
+    }
+
+    fn pause(&mut self) -> Result<(), super::Error> {
+        if !self.paused {
+            unsafe { self.prev.client()?.audio_client.Stop() }.map_err(Error::from)?;
+            self.paused = true;
+        }
         Ok(())
     }
 
-    fn output(&mut self, samples: &[f64]) -> Result<(), super::Error> {
-        let samples = samples[0..self.prev.channels as usize].to_vec();
-        self.prev.samples.push_back(samples);
+    fn resume(&mut self) -> Result<(), super::Error> {
+        if self.paused {
+            unsafe { self.prev.client()?.audio_client.Start() }.map_err(Error::from)?;
+            self.paused = false;
+        }
+        Ok(())
+    }
 
-        if self.prev.samples.len() >= self.prev.buffer_size as usize {
-            if unsafe {
-                WaitForSingleObject(
-                    self.prev.event_handle,
-                    if self.blocking { INFINITE } else { 0 },
-                )
-            } == WAIT_OBJECT_0
-            {
-                self.prev.write()?;
-            } else {
-                return Err(super::Error::WASAPIError(Error::WaitTimeout));
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn drain(&mut self) -> Result<(), super::Error> {
+        self.resume()?;
+
+        let threshold = self.prev.buffer_size as usize * self.prev.channels as usize;
+        while self.prev.consumer.len() >= threshold {
+            self.wait(true)?;
+            self.prev.write()?;
+        }
+
+        // exclusive mode only takes whole buffers, pad the tail with silence
+        if self.prev.exclusive && !self.prev.consumer.is_empty() {
+            let silence = vec![0.0; threshold - self.prev.consumer.len()];
+            self.prev.producer.push_slice(&silence);
+        }
+
+        while !self.prev.consumer.is_empty() {
+            self.wait(true)?;
+            self.prev.write()?;
+        }
+
+        // then let the device play out its own buffer
+        let frequency = self.prev.frequency as f64;
+        for _ in 0..4 {
+            let padding = unsafe { self.prev.client()?.audio_client.GetCurrentPadding() }
+                .map_err(Error::from)?;
+            if padding == 0 {
+                break;
             }
+            thread::sleep(Duration::from_secs_f64(padding as f64 / frequency));
         }
+        self.prev.started = false;
 
         Ok(())
     }
 
-    fn output_i16(&mut self, samples: &[i16]) -> Result<(), super::Error> {
-        let samples = samples[0..self.prev.channels as usize]
-            .iter()
-            .map(|&x| x as f64 / 32768.0)
-            .collect();
-        self.prev.samples.push_back(samples);
+    fn flush(&mut self) -> Result<(), super::Error> {
+        self.prev.clear()?;
+        if !self.paused {
+            unsafe { self.prev.client()?.audio_client.Start() }.map_err(Error::from)?;
+        }
+        Ok(())
+    }
 
-        if self.prev.samples.len() >= self.prev.buffer_size as usize {
-            if unsafe {
-                WaitForSingleObject(
-                    self.prev.event_handle,
-                    if self.blocking { INFINITE } else { 0 },
-                )
-            } == WAIT_OBJECT_0
-            {
-                self.prev.write()?;
-            } else {
-                return Err(super::Error::WASAPIError(Error::WaitTimeout));
+    fn refresh_devices(&mut self) -> Result<(), super::Error> {
+        self.devices = enumerate(&self.enumlator)?;
+        Ok(())
+    }
+
+    fn reopen(&mut self, id: Option<&str>) -> Result<(), super::Error> {
+        // the default may have moved to another endpoint with the lost one
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => self.default_device_id()?,
+        };
+
+        let mut pending = vec![0.0; self.prev.consumer.len()];
+        self.prev.consumer.pop_slice(&mut pending);
+
+        match self.open(&id, self.prev.exclusive, self.prev.latency) {
+            Ok(mut prev) => {
+                prev.stats = std::mem::take(&mut self.prev.stats);
+                if prev.channels != self.prev.channels {
+                    prev.stats.dropped_frames +=
+                        (pending.len() / self.prev.channels as usize) as u64;
+                    pending.clear();
+                }
+                self.replace(prev);
+                self.prev.producer.push_slice(&pending);
+                self.current_device_id = id;
+                Ok(())
+            }
+            Err(err) => {
+                self.prev.producer.push_slice(&pending);
+                Err(err.into())
             }
         }
+    }
 
-        Ok(())
+    fn output_frames(&mut self, samples: &[f32]) -> Result<(), super::Error> {
+        let channels = self.prev.channels as usize;
+        let samples = &samples[..samples.len() / channels * channels];
+
+        if self.paused {
+            // the device isn't pulling, keep what fits until resume
+            let count = self.prev.producer.push_slice(samples);
+            self.prev.stats.dropped_frames += ((samples.len() - count) / channels) as u64;
+            return Ok(());
+        }
+
+        self.queue(samples)
+    }
+
+    fn take_stats(&mut self) -> AudioStats {
+        std::mem::take(&mut self.prev.stats)
     }
 }
@@ -0,0 +1,231 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::{AudioDriverType, DeviceInfo, Error};
+
+// how long the watcher thread waits for a change before checking if it should stop
+const WAIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Where the watcher gets its device list from, drivers provide one per
+// backend and tests can feed a fake one.
+pub trait DeviceSource: Send {
+    fn devices(&mut self) -> Result<Vec<DeviceInfo>, Error>;
+
+    // blocks for at most `timeout`, true means the list may have changed
+    fn wait(&mut self, timeout: Duration) -> bool;
+}
+
+impl<S: DeviceSource + ?Sized> DeviceSource for Box<S> {
+    fn devices(&mut self) -> Result<Vec<DeviceInfo>, Error> {
+        (**self).devices()
+    }
+
+    fn wait(&mut self, timeout: Duration) -> bool {
+        (**self).wait(timeout)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceEvent {
+    Added(DeviceInfo),
+    Removed(DeviceInfo),
+    DefaultChanged(DeviceInfo),
+}
+
+// never changes, for `AudioDriverType::None`
+struct NoDevices;
+
+impl DeviceSource for NoDevices {
+    fn devices(&mut self) -> Result<Vec<DeviceInfo>, Error> {
+        Ok(Vec::new())
+    }
+
+    fn wait(&mut self, timeout: Duration) -> bool {
+        thread::sleep(timeout);
+        false
+    }
+}
+
+// Watches the device list on its own thread and reports what changed, so a
+// settings page can refresh and an app can follow the default device.
+pub struct DeviceWatcher {
+    devices: Arc<Mutex<Vec<DeviceInfo>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DeviceWatcher {
+    pub fn new<F>(ty: AudioDriverType, callback: F) -> Result<DeviceWatcher, Error>
+    where
+        F: FnMut(DeviceEvent) + Send + 'static,
+    {
+        let source: Box<dyn DeviceSource> = match ty {
+            #[cfg(target_os = "windows")]
+            AudioDriverType::WASAPI => Box::new(super::wasapi::WASAPIDeviceSource::new()),
+            #[cfg(target_os = "linux")]
+            AudioDriverType::ALSA => Box::new(super::alsa::ALSADeviceSource::new()?),
+            _ => Box::new(NoDevices),
+        };
+        DeviceWatcher::with_source(source, callback)
+    }
+
+    // the first list is read before returning, events only cover later changes
+    pub fn with_source<S, F>(mut source: S, mut callback: F) -> Result<DeviceWatcher, Error>
+    where
+        S: DeviceSource + 'static,
+        F: FnMut(DeviceEvent) + Send + 'static,
+    {
+        let devices = Arc::new(Mutex::new(source.devices()?));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let devices = devices.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::Acquire) {
+                    if !source.wait(WAIT_TIMEOUT) {
+                        continue;
+                    }
+                    // a device that is still settling may fail to list, the
+                    // next change will pick it up
+                    let Ok(new) = source.devices() else {
+                        continue;
+                    };
+                    let events = {
+                        let mut devices = devices.lock().unwrap();
+                        let events = diff(&devices, &new);
+                        *devices = new;
+                        events
+                    };
+                    for event in events {
+                        callback(event);
+                    }
+                }
+            })
+        };
+
+        Ok(DeviceWatcher {
+            devices,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    // the list as of the last change
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.devices.lock().unwrap().clone()
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// devices are matched by id, capabilities aren't probed so they never differ
+fn diff(old: &[DeviceInfo], new: &[DeviceInfo]) -> Vec<DeviceEvent> {
+    let mut events = Vec::new();
+
+    for device in old {
+        if !new.iter().any(|other| other.id == device.id) {
+            events.push(DeviceEvent::Removed(device.clone()));
+        }
+    }
+    for device in new {
+        if !old.iter().any(|other| other.id == device.id) {
+            events.push(DeviceEvent::Added(device.clone()));
+        }
+    }
+
+    let old_default = old.iter().find(|device| device.is_default);
+    let new_default = new.iter().find(|device| device.is_default);
+    if let Some(device) = new_default {
+        if old_default.is_none_or(|old| old.id != device.id) {
+            events.push(DeviceEvent::DefaultChanged(device.clone()));
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::DeviceCapabilities;
+    use std::sync::mpsc;
+
+    fn device(id: &str, is_default: bool) -> DeviceInfo {
+        DeviceInfo {
+            id: id.to_string(),
+            name: id.to_string(),
+            description: String::new(),
+            is_default,
+            capabilities: DeviceCapabilities::default(),
+        }
+    }
+
+    // hands out one list per change, then stays quiet
+    struct FakeSource {
+        lists: mpsc::Receiver<Vec<DeviceInfo>>,
+        next: Option<Vec<DeviceInfo>>,
+    }
+
+    impl DeviceSource for FakeSource {
+        fn devices(&mut self) -> Result<Vec<DeviceInfo>, Error> {
+            Ok(self.next.take().unwrap_or_default())
+        }
+
+        fn wait(&mut self, timeout: Duration) -> bool {
+            self.next = self.lists.recv_timeout(timeout).ok();
+            self.next.is_some()
+        }
+    }
+
+    #[test]
+    fn diff_reports_changes() {
+        let old = [device("a", true), device("b", false)];
+        let new = [device("b", true), device("c", false)];
+
+        assert_eq!(
+            diff(&old, &new),
+            [
+                DeviceEvent::Removed(device("a", true)),
+                DeviceEvent::Added(device("c", false)),
+                DeviceEvent::DefaultChanged(device("b", true)),
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn watcher_follows_source() {
+        let (lists, receiver) = mpsc::channel();
+        let source = FakeSource {
+            lists: receiver,
+            next: Some(vec![device("a", true)]),
+        };
+        let (events, received) = mpsc::channel();
+        let watcher = DeviceWatcher::with_source(source, move |event| {
+            let _ = events.send(event);
+        })
+        .unwrap();
+        assert_eq!(watcher.devices(), [device("a", true)]);
+
+        lists
+            .send(vec![device("a", true), device("b", false)])
+            .unwrap();
+        let event = received.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(event, DeviceEvent::Added(device("b", false)));
+        assert_eq!(watcher.devices().len(), 2);
+    }
+}